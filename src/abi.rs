@@ -0,0 +1,251 @@
+use anyhow::{anyhow, Result};
+use nix::libc;
+use nix::sys::ptrace;
+use nix::unistd::Pid;
+
+use crate::{
+    registers,
+    tracee::Tracee,
+    types::{Type, TypeId, TypeTable},
+};
+
+// How the System V x86-64 ABI passes each eightbyte of a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    // in the general purpose registers, like integers and pointers
+    Integer,
+    // in the low half of an `xmm` register, like `float` and `double`
+    Sse,
+    // in the high half of the `xmm` register of the `Sse` eightbyte before, like the end of a `__float128`
+    SseUp,
+    // `long double`, returned in `st0`
+    X87,
+    // padding, or a struct that is entirely empty
+    Empty,
+    // in memory: on the stack for arguments, through a pointer given by the caller for return values
+    Memory,
+}
+
+// The class of each eightbyte of a value of the type, or a single `Memory` when it doesn't fit in registers
+pub fn classify(types: &TypeTable, ty: TypeId) -> Result<Vec<Class>> {
+    // `_Complex long double` is returned in `st0` and `st1`, which isn't supported
+    if let Type::Base {
+        encoding: gimli::DW_ATE_complex_float,
+        size: 32,
+        name,
+    } = types.get(types.strip(ty))
+        && !is_float128(name)
+    {
+        return Err(anyhow!("Unsupported type {}", types.name(ty)));
+    }
+
+    let size = types.size(ty);
+    if size > 16 {
+        return Ok(vec![Class::Memory]);
+    }
+    let mut classes = vec![Class::Empty; size.div_ceil(8)];
+    if !classify_at(types, ty, 0, &mut classes) {
+        return Ok(vec![Class::Memory]);
+    }
+
+    // the two halves of a `long double` are returned together in `st0`,
+    // anything sharing their eightbytes makes it go in memory
+    if classes == [Class::X87, Class::X87] {
+        return Ok(vec![Class::X87]);
+    }
+    if classes.contains(&Class::Memory) || classes.contains(&Class::X87) {
+        return Ok(vec![Class::Memory]);
+    }
+    // the high half of an `xmm` register can't be used without its low half
+    if classes == [Class::Integer, Class::SseUp] {
+        classes[1] = Class::Sse;
+    }
+    Ok(classes)
+}
+
+// `__float128` and `_Float128` are IEEE quadruple precision numbers,
+// unlike the x87 `long double` of the same size
+fn is_float128(name: &str) -> bool {
+    name.ends_with("float128") || name.ends_with("Float128")
+}
+
+// Merges the classes of the scalars of the type found at `offset` into those of their eightbytes.
+// Returns false for the scalars that aren't aligned, which make the whole value go in memory.
+fn classify_at(types: &TypeTable, ty: TypeId, offset: usize, classes: &mut [Class]) -> bool {
+    let ty = types.strip(ty);
+    let size = types.size(ty);
+    let (low, high) = match types.get(ty) {
+        Type::Void | Type::Function { .. } => return true,
+        Type::Struct { members, .. } => {
+            return members.iter().all(|member| {
+                // bitfields share the eightbyte of the integer they are carved from
+                classify_at(types, member.ty, offset + member.offset, classes)
+            });
+        }
+        Type::Array { elem, count } => {
+            let elem_size = types.size(*elem);
+            return (0..count.unwrap_or(0))
+                .all(|i| classify_at(types, *elem, offset + i * elem_size, classes));
+        }
+        Type::Base {
+            encoding: gimli::DW_ATE_float,
+            size: 16,
+            name,
+        } if is_float128(name) => (Class::Sse, Class::SseUp),
+        Type::Base {
+            encoding: gimli::DW_ATE_float,
+            size: 16,
+            ..
+        } => (Class::X87, Class::X87),
+        Type::Base {
+            encoding: gimli::DW_ATE_float,
+            ..
+        } => (Class::Sse, Class::Sse),
+        // the real and imaginary parts, each a `float` or a `double`
+        Type::Base {
+            encoding: gimli::DW_ATE_complex_float,
+            ..
+        } => {
+            let part = size / 2;
+            return merge_scalar(classes, offset, part, Class::Sse, Class::Sse)
+                && merge_scalar(classes, offset + part, part, Class::Sse, Class::Sse);
+        }
+        Type::Base { .. } | Type::Pointer { .. } | Type::Enum { .. } => {
+            (Class::Integer, Class::Integer)
+        }
+        Type::Typedef { .. } | Type::Qualified { .. } => unreachable!("stripped above"),
+    };
+    merge_scalar(classes, offset, size, low, high)
+}
+
+// Merges the classes of a scalar of `size` bytes found at `offset` into those of the eightbytes
+// it spans, `high` being the class of its second eightbyte.
+// Returns false if the scalar isn't aligned.
+fn merge_scalar(
+    classes: &mut [Class],
+    offset: usize,
+    size: usize,
+    low: Class,
+    high: Class,
+) -> bool {
+    if size == 0 {
+        return true;
+    }
+    if !offset.is_multiple_of(size.min(8)) {
+        return false;
+    }
+
+    // `__int128`, `long double` and `__float128` span two eightbytes
+    let eightbytes = &mut classes[offset / 8..(offset + size).div_ceil(8)];
+    for (i, class) in eightbytes.iter_mut().enumerate() {
+        let new = if i == 0 { low } else { high };
+        *class = match (*class, new) {
+            (old, new) if old == new => old,
+            (Class::Empty, new) => new,
+            (old, Class::Empty) => old,
+            (Class::Memory, _) | (_, Class::Memory) => Class::Memory,
+            (Class::Integer, _) | (_, Class::Integer) => Class::Integer,
+            (Class::X87, _) | (_, Class::X87) => Class::Memory,
+            (Class::Sse | Class::SseUp, Class::Sse | Class::SseUp) => Class::Sse,
+        };
+    }
+    true
+}
+
+// The bytes of the value of the type a function has just returned, read from the registers
+// the ABI returns it in. Values returned in memory are found through the pointer left in `rax`.
+pub fn get_return_value(tracee: &Tracee, types: &TypeTable, ty: TypeId) -> Result<Vec<u8>> {
+    let size = types.size(ty);
+    let regs = ptrace::getregs(tracee.pid)?;
+    let fp_regs = registers::get_fp_regs(tracee.pid)?;
+
+    let classes = classify(types, ty)?;
+    match classes[..] {
+        [Class::Memory] => return tracee.read_bytes(regs.rax, size),
+        [Class::X87] => {
+            // the 80 bits of `st0`, in the 16 bytes of a `long double`
+            let mut bytes = fp_regs.st_space[..4]
+                .iter()
+                .flat_map(|word| word.to_le_bytes())
+                .collect::<Vec<_>>();
+            bytes[10..].fill(0);
+            return Ok(bytes);
+        }
+        _ => {}
+    }
+
+    // the eightbytes are returned in `rax` then `rdx`, or `xmm0` then `xmm1`,
+    // all of `xmm0` for a `__float128`
+    let mut int_regs = [regs.rax, regs.rdx].into_iter();
+    let mut sse_regs = (0..2).map(|n| registers::get_xmm_low(&fp_regs, n));
+    let mut bytes = Vec::with_capacity(classes.len() * 8);
+    for class in classes {
+        let eightbyte = match class {
+            Class::Integer => int_regs.next(),
+            Class::Sse => sse_regs.next(),
+            Class::SseUp => Some(registers::get_xmm_high(&fp_regs, 0)),
+            Class::Empty => Some(0),
+            Class::X87 | Class::Memory => None,
+        }
+        .ok_or(anyhow!("Unsupported return type"))?;
+        bytes.extend(eightbyte.to_le_bytes());
+    }
+    bytes.truncate(size);
+    Ok(bytes)
+}
+
+// Puts the bytes of a value of the type where a function returns it, as if it had just returned it
+pub fn set_return_value(pid: Pid, types: &TypeTable, ty: TypeId, bytes: &[u8]) -> Result<()> {
+    let mut regs = ptrace::getregs(pid)?;
+    let mut fp_regs = registers::get_fp_regs(pid)?;
+
+    let classes = classify(types, ty)?;
+    match classes[..] {
+        // the caller's buffer is only known to the function
+        [Class::Memory] => {
+            return Err(anyhow!(
+                "Cannot set the return value of a function returning {} in memory",
+                types.name(ty)
+            ))
+        }
+        [Class::X87] => push_x87(&mut fp_regs, bytes),
+        _ => {
+            let mut int_regs = [&mut regs.rax, &mut regs.rdx].into_iter();
+            let mut sse_regs = 0..2;
+            for (class, chunk) in classes.iter().zip(bytes.chunks(8)) {
+                let mut eightbyte = [0; 8];
+                eightbyte[..chunk.len()].copy_from_slice(chunk);
+                let eightbyte = u64::from_le_bytes(eightbyte);
+                match class {
+                    Class::Integer => *int_regs.next().expect("at most two eightbytes") = eightbyte,
+                    Class::Sse => registers::set_xmm_low(
+                        &mut fp_regs,
+                        sse_regs.next().expect("at most two eightbytes"),
+                        eightbyte,
+                    ),
+                    Class::SseUp => registers::set_xmm_high(&mut fp_regs, 0, eightbyte),
+                    Class::X87 | Class::Empty | Class::Memory => {}
+                }
+            }
+        }
+    }
+
+    ptrace::setregs(pid, regs)?;
+    registers::set_fp_regs(pid, &fp_regs)
+}
+
+// Pushes the `long double` onto the x87 register stack, making it `st0`
+fn push_x87(fp_regs: &mut libc::user_fpregs_struct, bytes: &[u8]) {
+    // the registers are saved in stack order, `st7` falls off the end
+    fp_regs.st_space.copy_within(..28, 4);
+    let mut st0 = [0; 16];
+    st0[..10].copy_from_slice(&bytes[..10]);
+    for (word, chunk) in fp_regs.st_space[..4].iter_mut().zip(st0.chunks(4)) {
+        *word = u32::from_le_bytes(chunk.try_into().expect("chunks of 4 bytes"));
+    }
+
+    // the top of the stack moves down a physical register, which becomes valid
+    let top = (((fp_regs.swd >> 11) & 0x7) + 7) & 0x7;
+    fp_regs.swd = (fp_regs.swd & !(0x7 << 11)) | (top << 11);
+    fp_regs.ftw |= 1 << top;
+}
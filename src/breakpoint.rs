@@ -1,8 +1,9 @@
 use anyhow::Result;
 use nix::{sys::ptrace, unistd::Pid};
-use owo_colors::OwoColorize;
 
-const INT3: i64 = 0xcc;
+use crate::memory;
+
+pub const INT3: u8 = 0xcc;
 
 #[derive(Debug)]
 pub struct Breakpoint {
@@ -23,29 +24,21 @@ impl Breakpoint {
     }
 
     pub fn enable(&mut self) -> Result<()> {
-        let data = ptrace::read(self.t_pid, self.inst_addr)?;
-        println!("data: {:x?}", data.to_le_bytes().green());
-        let data_with_int3 = (data & !0xff) | INT3;
-        unsafe {
-            ptrace::write(
-                self.t_pid,
-                self.inst_addr,
-                data_with_int3 as ptrace::AddressType,
-            )?;
-        }
+        let data = memory::read_bytes(self.t_pid, self.inst_addr as u64, 1)?[0];
+        memory::write_bytes(self.t_pid, self.inst_addr as u64, &[INT3])?;
 
-        self.inst_data = Some((data & 0xff) as u8);
+        self.inst_data = Some(data);
         self.enabled = true;
 
         Ok(())
     }
 
     pub fn disable(&mut self) -> Result<()> {
-        let data = ptrace::read(self.t_pid, self.inst_addr)?;
-        let orig_data = (data & !0xff) | self.inst_data.unwrap() as i64;
-        unsafe {
-            ptrace::write(self.t_pid, self.inst_addr, orig_data as ptrace::AddressType)?;
-        }
+        memory::write_bytes(
+            self.t_pid,
+            self.inst_addr as u64,
+            &[self.inst_data.unwrap()],
+        )?;
 
         self.inst_data = None; // I don't suppose this is needed
         self.enabled = false;
@@ -60,6 +53,46 @@ impl Breakpoint {
     pub fn get_addr(&self) -> ptrace::AddressType {
         self.inst_addr
     }
+
+    // The instruction byte replaced by the int3, while the breakpoint is enabled
+    pub fn saved_byte(&self) -> Option<u8> {
+        self.inst_data.filter(|_| self.enabled)
+    }
+
+    pub fn set_saved_byte(&mut self, data: u8) {
+        self.inst_data = Some(data);
+    }
+}
+
+// A breakpoint as seen by the user, identified by its number.
+// Several of these can be set at the same address, in which case they share a single `Breakpoint`,
+// which stays enabled as long as any one of them is.
+#[derive(Debug)]
+pub struct UserBreakpoint {
+    pub id: usize,
+    pub addr: ptrace::AddressType,
+    pub enabled: bool,
+    pub hit_count: usize,
+    // the breakpoint only stops the tracee when this holds
+    pub condition: Option<String>,
+    // number of upcoming hits that are not going to stop the tracee
+    pub ignore_count: usize,
+    // hardware breakpoints live in a debug register instead of sharing an int3
+    pub slot: Option<usize>,
+}
+
+impl UserBreakpoint {
+    pub fn new(id: usize, addr: ptrace::AddressType) -> Self {
+        Self {
+            id,
+            addr,
+            enabled: true,
+            hit_count: 0,
+            condition: None,
+            ignore_count: 0,
+            slot: None,
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -0,0 +1,70 @@
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "A ptrace based debugger for x86-64 Linux")]
+pub struct Args {
+    /// Path to the program to debug
+    #[arg(required_unless_present = "pid")]
+    pub program: Option<PathBuf>,
+
+    /// Arguments passed to the program (everything after `--`)
+    #[arg(last = true)]
+    pub args: Vec<String>,
+
+    /// Attach to an already running process instead of launching a program
+    #[arg(
+        short,
+        long,
+        conflicts_with_all = ["program", "args", "env", "cwd", "stdin", "stdout", "stderr", "aslr", "no_aslr"]
+    )]
+    pub pid: Option<i32>,
+
+    /// Set an environment variable for the program, can be repeated
+    #[arg(short, long = "env", value_name = "KEY=VAL", value_parser = parse_env_var)]
+    pub env: Vec<(String, String)>,
+
+    /// Working directory of the program
+    #[arg(long)]
+    pub cwd: Option<PathBuf>,
+
+    /// Redirect the program's stdin from this file
+    #[arg(long)]
+    pub stdin: Option<PathBuf>,
+
+    /// Redirect the program's stdout to this file
+    #[arg(long)]
+    pub stdout: Option<PathBuf>,
+
+    /// Redirect the program's stderr to this file
+    #[arg(long)]
+    pub stderr: Option<PathBuf>,
+
+    /// Keep address space layout randomisation enabled for the program
+    #[arg(long, overrides_with = "no_aslr")]
+    aslr: bool,
+
+    /// Disable address space layout randomisation for the program (default)
+    #[arg(long = "no-aslr", overrides_with = "aslr")]
+    no_aslr: bool,
+}
+
+impl Args {
+    pub fn aslr(&self) -> bool {
+        // ASLR is disabled unless explicitly asked for,
+        // so that addresses stay the same across runs
+        self.aslr && !self.no_aslr
+    }
+}
+
+fn parse_env_var(s: &str) -> Result<(String, String)> {
+    let (key, val) = s.split_once('=').ok_or(anyhow!(
+        "Environment variable must be of the form KEY=VAL: {}",
+        s
+    ))?;
+    if key.is_empty() {
+        return Err(anyhow!("Environment variable name can't be empty"));
+    }
+    Ok((key.to_owned(), val.to_owned()))
+}
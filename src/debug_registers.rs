@@ -0,0 +1,148 @@
+use anyhow::{anyhow, Result};
+use nix::{libc, sys::ptrace, unistd::Pid};
+use std::mem::MaybeUninit;
+
+// x86-64 has four debug registers (DR0 - DR3) that can hold addresses,
+// DR6 reports which of them fired and DR7 controls what they watch
+pub const N_SLOTS: usize = 4;
+const DR6: usize = 6;
+const DR7: usize = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Execute,
+    Write,
+    // x86 can't trap on reads alone, only on reads and writes
+    ReadWrite,
+}
+
+#[derive(Debug, Default)]
+pub struct DebugRegisters {
+    used: [bool; N_SLOTS],
+}
+
+impl DebugRegisters {
+    // Programs a free debug register to trap on accesses of `len` bytes at `addr`,
+    // returning the slot used
+    pub fn set(&mut self, pid: Pid, addr: u64, len: usize, cond: Condition) -> Result<usize> {
+        let len_bits = match (cond, len) {
+            (Condition::Execute, 1) => 0b00,
+            (Condition::Execute, _) => {
+                return Err(anyhow!("Hardware breakpoints can only have a length of 1"))
+            }
+            (_, 1) => 0b00,
+            (_, 2) => 0b01,
+            (_, 8) => 0b10,
+            (_, 4) => 0b11,
+            _ => {
+                return Err(anyhow!(
+                    "Hardware watchpoints can only watch 1, 2, 4 or 8 bytes, not {}",
+                    len
+                ))
+            }
+        };
+        if !addr.is_multiple_of(len as u64) {
+            return Err(anyhow!(
+                "Address {:#x} is not aligned to the watched length {}",
+                addr,
+                len
+            ));
+        }
+        let rw_bits = match cond {
+            Condition::Execute => 0b00,
+            Condition::Write => 0b01,
+            Condition::ReadWrite => 0b11,
+        };
+
+        let slot = self
+            .used
+            .iter()
+            .position(|used| !used)
+            .ok_or(anyhow!(
+                "All {} hardware debug registers are in use, delete a hardware breakpoint or watchpoint first",
+                N_SLOTS
+            ))?;
+
+        write_debug_reg(pid, slot, addr)?;
+        let mut dr7 = read_debug_reg(pid, DR7)?;
+        dr7 &= !(0b1111 << (16 + slot * 4));
+        dr7 |= (rw_bits | len_bits << 2) << (16 + slot * 4);
+        dr7 |= 1 << (slot * 2);
+        write_debug_reg(pid, DR7, dr7)?;
+
+        self.used[slot] = true;
+        Ok(slot)
+    }
+
+    pub fn has_free_slot(&self) -> bool {
+        self.used.iter().any(|used| !used)
+    }
+
+    pub fn clear(&mut self, pid: Pid, slot: usize) -> Result<()> {
+        let mut dr7 = read_debug_reg(pid, DR7)?;
+        dr7 &= !(0b1111 << (16 + slot * 4));
+        dr7 &= !(0b11 << (slot * 2));
+        write_debug_reg(pid, DR7, dr7)?;
+        write_debug_reg(pid, slot, 0)?;
+
+        self.used[slot] = false;
+        Ok(())
+    }
+
+    pub fn clear_all(&mut self, pid: Pid) -> Result<()> {
+        for slot in 0..N_SLOTS {
+            if self.used[slot] {
+                self.clear(pid, slot)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Toggles the local enable bit of the slot, keeping its address and condition around
+    pub fn set_enabled(&self, pid: Pid, slot: usize, enabled: bool) -> Result<()> {
+        let mut dr7 = read_debug_reg(pid, DR7)?;
+        if enabled {
+            dr7 |= 1 << (slot * 2);
+        } else {
+            dr7 &= !(1 << (slot * 2));
+        }
+        write_debug_reg(pid, DR7, dr7)
+    }
+
+    // Returns the slots that caused the last debug exception, according to DR6, and resets DR6
+    pub fn take_hits(&self, pid: Pid) -> Result<Vec<usize>> {
+        let dr6 = read_debug_reg(pid, DR6)?;
+        write_debug_reg(pid, DR6, 0)?;
+
+        let hits = (0..N_SLOTS)
+            .filter(|&slot| self.used[slot] && dr6 & (1 << slot) != 0)
+            .collect();
+        Ok(hits)
+    }
+}
+
+// Whether a debug register is able to watch `len` bytes at `addr`
+pub fn can_watch(addr: u64, len: usize) -> bool {
+    matches!(len, 1 | 2 | 4 | 8) && addr.is_multiple_of(len as u64)
+}
+
+fn debug_reg_offset(n: usize) -> ptrace::AddressType {
+    let user = MaybeUninit::<libc::user>::uninit();
+    let base = user.as_ptr();
+    // SAFETY: only the address of the field is computed, the uninitialised memory is never read
+    let debugreg = unsafe { std::ptr::addr_of!((*base).u_debugreg) };
+    let offset = debugreg as usize - base as usize + n * std::mem::size_of::<u64>();
+    offset as ptrace::AddressType
+}
+
+fn read_debug_reg(pid: Pid, n: usize) -> Result<u64> {
+    let val = ptrace::read_user(pid, debug_reg_offset(n))?;
+    Ok(val as u64)
+}
+
+fn write_debug_reg(pid: Pid, n: usize, val: u64) -> Result<()> {
+    unsafe {
+        ptrace::write_user(pid, debug_reg_offset(n), val as ptrace::AddressType)?;
+    }
+    Ok(())
+}
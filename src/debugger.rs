@@ -1,25 +1,83 @@
 use anyhow::{anyhow, Result};
-use nix::sys::ptrace;
-use std::collections::HashMap;
+use nix::sys::{
+    ptrace,
+    signal::{self, Signal},
+    wait,
+};
+use nix::unistd::Pid;
+use std::collections::{BTreeMap, HashSet};
 use std::io::stdin;
 
 use crate::{
-    breakpoint::{Breakpoint, BreakpointLaterAction},
-    dwarf, registers,
+    abi,
+    breakpoint::{Breakpoint, BreakpointLaterAction, UserBreakpoint},
+    cli::Args,
+    debug_registers::{self, Condition, DebugRegisters},
+    disasm, dwarf,
+    examine::{self, ExamineFormat},
+    expr::{self, Storage, Value},
+    location::Location,
+    ptype, registers,
+    skip::{Skip, SkipKind},
     tracee::Tracee,
-    util,
+    types::{self, Type, TypeId, TypeTable},
+    unwind::{Frame, Unwinder},
+    variable,
+    watchpoint::{self, WatchKind, Watchpoint},
 };
 
+// si_code of a SIGTRAP caused by the debug registers
+const TRAP_HWBKPT: i32 = 0x4;
+
 pub struct Debugger {
     tracee: Tracee,
-    breakpoints: HashMap<ptrace::AddressType, Breakpoint>,
+    // whether the tracee was attached to, rather than launched
+    attached: bool,
+    user_breakpoints: BTreeMap<usize, UserBreakpoint>,
+    next_breakpoint_id: usize,
+    // addresses of the breakpoints set internally while stepping
+    temp_breakpoints: HashSet<ptrace::AddressType>,
+    debug_registers: DebugRegisters,
+    // watchpoints share their numbering with the user breakpoints
+    watchpoints: BTreeMap<usize, Watchpoint>,
+    // what `step` doesn't stop in
+    skips: Vec<Skip>,
+    next_skip_id: usize,
+    unwinder: Unwinder,
+    // frame inspected by `registers read` and `lines`, 0 being the innermost one.
+    // Goes back to 0 whenever the tracee runs.
+    selected_frame: usize,
+    types: TypeTable,
+    // format of the last `x`, and the address following the memory it showed
+    examine_format: ExamineFormat,
+    next_examine_addr: Option<u64>,
+    // the values returned by `finish`, `$1` being the first one
+    history: Vec<Value>,
 }
 
 impl Debugger {
-    pub fn new(path: &str) -> Result<Self> {
+    pub fn new(args: &Args) -> Result<Self> {
+        let tracee = if let Some(pid) = args.pid {
+            Tracee::attach(Pid::from_raw(pid))?
+        } else {
+            Tracee::new(args)?
+        };
         let debugger = Self {
-            tracee: Tracee::new(path)?,
-            breakpoints: HashMap::new(),
+            tracee,
+            attached: args.pid.is_some(),
+            user_breakpoints: BTreeMap::new(),
+            next_breakpoint_id: 1,
+            temp_breakpoints: HashSet::new(),
+            debug_registers: DebugRegisters::default(),
+            watchpoints: BTreeMap::new(),
+            skips: Vec::new(),
+            next_skip_id: 1,
+            unwinder: Unwinder::default(),
+            selected_frame: 0,
+            types: TypeTable::default(),
+            examine_format: ExamineFormat::default(),
+            next_examine_addr: None,
+            history: Vec::new(),
         };
         Ok(debugger)
     }
@@ -32,91 +90,835 @@ impl Debugger {
         loop {
             eprint!("> ");
             buffer.clear();
-            stdin().read_line(&mut buffer)?;
-
-            let mut split = buffer.trim_end().split(' ');
-            match split.next().ok_or(anyhow!("Empty command"))? {
-                "b" | "break" => {
-                    let addr =
-                        util::parse_hex(split.next().ok_or(anyhow!("No address provided"))?)?;
-                    // let addr = addr + self.tracee.start_load_addr;
-                    let addr = self.tracee.add_load_addr(addr);
-                    self.set_breakpoint_at(addr as ptrace::AddressType)?;
-                }
-                "c" | "cont" | "continue" => {
-                    if self.continue_execution()? {
-                        break;
+            // end of the input, e.g. Ctrl-D
+            if stdin().read_line(&mut buffer)? == 0 {
+                self.quit()?;
+                break;
+            }
+            match self.run_command(&buffer) {
+                Ok(true) => break,
+                Ok(false) => {}
+                // a failed command leaves the tracee as it was, ready for the next one
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+
+        Ok(())
+    }
+
+    // Runs the command read from the prompt.
+    // Returns true if the session is over, the tracee having exited or been left alone.
+    fn run_command(&mut self, buffer: &str) -> Result<bool> {
+        let mut split = buffer.trim_end().split(' ');
+        match split.next().ok_or(anyhow!("Empty command"))? {
+            "b" | "break" => {
+                let args = split.collect::<Vec<_>>().join(" ");
+                // the location can be an expression, the condition is everything after `if`
+                let (loc, condition) = match args.split_once(" if ") {
+                    Some((loc, condition)) => (loc, Some(condition.trim().to_owned())),
+                    None => (args.as_str(), None),
+                };
+                let loc: Location = loc.trim().parse()?;
+                let addr = self.resolve_location(&loc)?;
+                self.add_user_breakpoint(addr as ptrace::AddressType, condition)?;
+            }
+            "hbreak" => {
+                let loc: Location = split.collect::<Vec<_>>().join(" ").parse()?;
+                let addr = self.resolve_location(&loc)?;
+                self.add_hw_breakpoint(addr as ptrace::AddressType)?;
+            }
+            sc @ ("watch" | "rwatch" | "awatch") => {
+                let kind = match sc {
+                    "watch" => WatchKind::Write,
+                    "rwatch" => WatchKind::Read,
+                    _ => WatchKind::Access,
+                };
+                let arg = split.collect::<Vec<_>>().join(" ");
+                // `watch <addr> <len>` watches raw memory
+                let (expr, len) = match arg
+                    .split_once(' ')
+                    .map(|(expr, len)| (expr, len.parse::<usize>()))
+                {
+                    Some((expr, Ok(len))) => (expr, Some(len)),
+                    _ => (arg.as_str(), None),
+                };
+                let (addr, len) = self.with_expr_context(|ctx| {
+                    let value = ctx.evaluate(expr)?;
+                    match value.storage {
+                        // `watch counter`, the object the expression designates
+                        expr::Storage::Memory(addr) => {
+                            Ok((addr, len.unwrap_or(ctx.size_of(value.ty))))
+                        }
+                        _ => Ok((ctx.get_u64(&value)?, len.unwrap_or(8))),
                     }
+                })?;
+                self.add_watchpoint(addr, len, kind)?;
+            }
+            "condition" => {
+                let id = split
+                    .next()
+                    .ok_or(anyhow!("No breakpoint number provided"))?;
+                let condition = split.collect::<Vec<_>>().join(" ");
+                // no condition makes the breakpoint unconditional
+                let condition = (!condition.is_empty()).then_some(condition);
+                self.get_user_breakpoint(id.parse()?)?.condition = condition;
+            }
+            "ignore" => {
+                let id = split
+                    .next()
+                    .ok_or(anyhow!("No breakpoint number provided"))?;
+                let count = split.next().ok_or(anyhow!("No count provided"))?;
+                self.get_user_breakpoint(id.parse()?)?.ignore_count = count.parse()?;
+            }
+            "i" | "info" => {
+                let sc = split.next().ok_or(anyhow!("No subcommand provided"))?;
+                match sc {
+                    "b" | "break" | "breakpoints" => self.print_breakpoints()?,
+                    "locals" => self.print_frame_variables(false)?,
+                    "skip" => self.print_skips(),
+                    "args" => self.print_frame_variables(true)?,
+                    _ => return Err(anyhow!("Unknown subcommand: {}", sc)),
                 }
-                "si" | "stepi" => self.single_step_instr_with_breakpoint_check()?,
-                "step" => self.step_in()?,
-                "next" => self.step_over()?,
-                "finish" => self.step_out()?,
-                "r" | "reg" | "registers" => {
-                    let sc = split.next().ok_or(anyhow!("No subcommand provided"))?;
-                    match sc {
-                        "d" | "dump" => {
-                            let regs = ptrace::getregs(self.tracee.pid)?;
-                            println!("REGS: {:#x?}", regs);
-                        }
-                        "r" | "read" => {
-                            let reg = split.next().ok_or(anyhow!("No register provided"))?;
-                            let reg = registers::get_reg_from_string(reg)?;
-                            println!("{:#x}", registers::get_reg_value(self.tracee.pid, reg)?);
-                        }
-                        "w" | "write" => {
-                            let reg = split.next().ok_or(anyhow!("No register provided"))?;
-                            let reg = registers::get_reg_from_string(reg)?;
-                            let value =
-                                util::parse_hex(split.next().ok_or(anyhow!("No value provided"))?)?;
-                            registers::set_reg_value(self.tracee.pid, reg, value as u64)?;
-                        }
-                        _ => return Err(anyhow!("Unknown subcommand: {}", sc)),
+            }
+            "d" | "delete" => {
+                let ids = split.map(str::parse).collect::<Result<Vec<_>, _>>()?;
+                // delete all the breakpoints if none are specified
+                let ids = if ids.is_empty() {
+                    self.user_breakpoints
+                        .keys()
+                        .chain(self.watchpoints.keys())
+                        .copied()
+                        .collect()
+                } else {
+                    ids
+                };
+                for id in ids {
+                    self.delete_user_breakpoint(id)?;
+                }
+            }
+            "disable" => {
+                for id in split {
+                    self.set_user_breakpoint_enabled(id.parse()?, false)?;
+                }
+            }
+            "enable" => {
+                for id in split {
+                    self.set_user_breakpoint_enabled(id.parse()?, true)?;
+                }
+            }
+            "c" | "cont" | "continue" => {
+                if self.continue_execution()? {
+                    return Ok(true);
+                }
+            }
+            "si" | "stepi" => self.single_step_instr_with_breakpoint_check()?,
+            "ni" | "nexti" => {
+                if self.step_over_instr()? {
+                    return Ok(true);
+                }
+            }
+            "step" => {
+                if self.step_in()? {
+                    return Ok(true);
+                }
+            }
+            "skip" => {
+                let sc = split.next();
+                let arg = split.collect::<Vec<_>>().join(" ");
+                match sc {
+                    // `skip` alone skips the current function
+                    None | Some("function") => self.add_skip(SkipKind::Function, &arg)?,
+                    Some("file") => self.add_skip(SkipKind::File, &arg)?,
+                    Some("delete") => {
+                        let ids = arg
+                            .split_whitespace()
+                            .map(str::parse)
+                            .collect::<Result<Vec<usize>, _>>()?;
+                        // delete all of them if none are specified
+                        self.skips
+                            .retain(|skip| !ids.is_empty() && !ids.contains(&skip.id));
                     }
+                    Some(sc) => return Err(anyhow!("Unknown subcommand: {}", sc)),
+                }
+            }
+            "next" => {
+                if self.step_over()? {
+                    return Ok(true);
+                }
+            }
+            "finish" => {
+                if self.step_out()? {
+                    return Ok(true);
+                }
+            }
+            "u" | "until" => {
+                let arg = split.collect::<Vec<_>>().join(" ");
+                let exited = if arg.is_empty() {
+                    self.run_until_next_line()?
+                } else {
+                    let addr = self.resolve_location(&arg.parse()?)?;
+                    self.run_to_addrs(HashSet::from([addr]), true)?
+                };
+                if exited {
+                    return Ok(true);
+                }
+            }
+            "advance" => {
+                let loc: Location = split.collect::<Vec<_>>().join(" ").parse()?;
+                let addr = self.resolve_location(&loc)?;
+                // unlike `until`, also stops in recursive calls
+                if self.run_to_addrs(HashSet::from([addr]), false)? {
+                    return Ok(true);
+                }
+            }
+            "jump" => {
+                let loc: Location = split.collect::<Vec<_>>().join(" ").parse()?;
+                let addr = self.resolve_location(&loc)?;
+                if self.jump(addr)? {
+                    return Ok(true);
+                }
+            }
+            "return" => {
+                let expr = split.collect::<Vec<_>>().join(" ");
+                self.force_return(expr.trim())?;
+            }
+            "bt" | "backtrace" => self.print_backtrace()?,
+            "up" => {
+                let n: usize = split.next().map(str::parse).transpose()?.unwrap_or(1);
+                let frames = self.unwinder.backtrace(self.tracee.pid)?;
+                if self.selected_frame + 1 >= frames.len() {
+                    return Err(anyhow!("Initial frame selected; you cannot go up"));
                 }
-                "m" | "mem" | "memory" => {
-                    let sc = split.next().ok_or(anyhow!("No subcommand provided"))?;
-                    let addr =
-                        util::parse_hex(split.next().ok_or(anyhow!("No address provided"))?)?;
-                    match sc {
-                        "r" | "read" => {
-                            let val = self.tracee.read_mem(addr)?;
-                            // ptrace::read(self.tracee.pid, addr as ptrace::AddressType)?;
-                            println!("{:#x}", val);
+                let index = (self.selected_frame + n).min(frames.len() - 1);
+                self.select_frame(&frames, index)?;
+            }
+            "down" => {
+                let n: usize = split.next().map(str::parse).transpose()?.unwrap_or(1);
+                if self.selected_frame == 0 {
+                    return Err(anyhow!(
+                        "Bottom (innermost) frame selected; you cannot go down"
+                    ));
+                }
+                let frames = self.unwinder.backtrace(self.tracee.pid)?;
+                self.select_frame(&frames, self.selected_frame.saturating_sub(n))?;
+            }
+            "f" | "frame" => {
+                let index = split
+                    .next()
+                    .map(str::parse)
+                    .transpose()?
+                    .unwrap_or(self.selected_frame);
+                let frames = self.unwinder.backtrace(self.tracee.pid)?;
+                self.select_frame(&frames, index)?;
+            }
+            "r" | "reg" | "registers" => {
+                let sc = split.next().ok_or(anyhow!("No subcommand provided"))?;
+                match sc {
+                    "d" | "dump" => {
+                        let regs = ptrace::getregs(self.tracee.pid)?;
+                        println!("REGS: {:#x?}", regs);
+                    }
+                    "r" | "read" => {
+                        let reg = split.next().ok_or(anyhow!("No register provided"))?;
+                        let reg = registers::get_reg_from_string(reg)?;
+                        match self.get_frame_reg_value(reg)? {
+                            Some(value) => println!("{:#x}", value),
+                            // like the caller saved registers in the frames further out
+                            None => println!("<not saved>"),
                         }
-                        "w" | "write" => {
-                            let val =
-                                util::parse_hex(split.next().ok_or(anyhow!("No value provided"))?)?;
-                            self.tracee.write_mem(addr, val)?;
-                            // unsafe { ptrace::write(self.tracee.pid, addr as ptrace::AddressType, val as ptrace::AddressType)?; }
+                    }
+                    "w" | "write" => {
+                        let reg = split.next().ok_or(anyhow!("No register provided"))?;
+                        let reg = registers::get_reg_from_string(reg)?;
+                        if self.selected_frame != 0 {
+                            return Err(anyhow!(
+                                "Registers can only be written in the innermost frame"
+                            ));
                         }
-                        _ => return Err(anyhow!("Unknown subcommand: {}", sc)),
+                        let value = self.evaluate_u64(&split.collect::<Vec<_>>().join(" "))?;
+                        registers::set_reg_value(self.tracee.pid, reg, value)?;
                     }
+                    _ => return Err(anyhow!("Unknown subcommand: {}", sc)),
                 }
-                "l" | "lines" => self.tracee.print_source()?,
-                cmd => return Err(anyhow!("Empty / Unknown command: {}", cmd)),
-            };
-        }
+            }
+            "m" | "mem" | "memory" => {
+                let sc = split.next().ok_or(anyhow!("No subcommand provided"))?;
+                // the address can't contain spaces when followed by a value
+                let addr = match sc {
+                    "w" | "write" => split.next().unwrap_or_default().to_owned(),
+                    _ => split.by_ref().collect::<Vec<_>>().join(" "),
+                };
+                let addr = self.evaluate_u64(&addr)?;
+                match sc {
+                    "r" | "read" => {
+                        let val = self.tracee.read_mem(addr)?;
+                        println!("{:#x}", val);
+                    }
+                    "w" | "write" => {
+                        let val = self.evaluate_u64(&split.collect::<Vec<_>>().join(" "))?;
+                        self.tracee.write_mem(addr, val)?;
+                    }
+                    _ => return Err(anyhow!("Unknown subcommand: {}", sc)),
+                }
+            }
+            cmd if cmd == "x" || cmd.starts_with("x/") => {
+                let fmt = match cmd.strip_prefix("x/") {
+                    Some(spec) => ExamineFormat::parse(spec, self.examine_format)?,
+                    None => ExamineFormat {
+                        count: 1,
+                        ..self.examine_format
+                    },
+                };
+                let expr = split.collect::<Vec<_>>().join(" ");
+                // without an address, carries on after the memory shown last
+                let addr = if expr.is_empty() {
+                    self.next_examine_addr
+                        .ok_or(anyhow!("Argument required (starting display address)."))?
+                } else {
+                    self.evaluate_u64(&expr)?
+                };
+                let next = examine::examine(&self.tracee, &mut self.unwinder, addr, fmt)?;
+                self.examine_format = fmt;
+                self.next_examine_addr = Some(next);
+            }
+            "disas" | "disassemble" => {
+                let mut arg = split.collect::<Vec<_>>().join(" ");
+                let mut mods = disasm::Modifiers::default();
+                if let Some(rest) = arg.strip_prefix('/') {
+                    let (letters, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+                    mods = disasm::Modifiers::parse(letters)?;
+                    arg = rest.trim().to_owned();
+                }
+                self.disassemble(&arg, mods)?;
+            }
+            "p" | "print" => {
+                let expr = split.collect::<Vec<_>>().join(" ");
+                let value = self.with_expr_context(|ctx| {
+                    let value = ctx.evaluate(&expr)?;
+                    ctx.format(&value)
+                })?;
+                println!("{} = {}", expr, value);
+            }
+            "call" => {
+                let expr = split.collect::<Vec<_>>().join(" ");
+                // unlike `print`, shows nothing for functions returning `void`
+                let value = self.with_expr_context(|ctx| {
+                    let value = ctx.evaluate(&expr)?;
+                    if ctx.is_void(value.ty) {
+                        return Ok(None);
+                    }
+                    ctx.format(&value).map(Some)
+                })?;
+                if let Some(value) = value {
+                    println!("{} = {}", expr, value);
+                }
+            }
+            "set" => {
+                let sc = split.next().ok_or(anyhow!("No subcommand provided"))?;
+                match sc {
+                    "var" | "variable" => {
+                        let expr = split.collect::<Vec<_>>().join(" ");
+                        self.with_expr_context(|ctx| ctx.evaluate(&expr))?;
+                    }
+                    _ => return Err(anyhow!("Unknown subcommand: {}", sc)),
+                }
+            }
+            sc @ ("ptype" | "whatis") => {
+                let mut arg = split.collect::<Vec<_>>().join(" ");
+                let offsets = sc == "ptype" && arg.starts_with("/o");
+                if offsets {
+                    arg = arg["/o".len()..].trim_start().to_owned();
+                }
+                if arg.is_empty() {
+                    return Err(anyhow!("No type or variable provided"));
+                }
+                let (ty, is_type_name) = self.resolve_type_or_expression(&arg)?;
+                if sc == "ptype" {
+                    println!("{}", ptype::ptype(&self.types, ty, offsets));
+                } else {
+                    println!("{}", ptype::whatis(&self.types, ty, is_type_name));
+                }
+            }
+            "l" | "lines" => {
+                let pc = self.get_frame_lookup_pc()?;
+                self.tracee.print_source_at(pc)?;
+            }
+            "attach" => {
+                let pid = split.next().ok_or(anyhow!("No pid provided"))?.parse()?;
+                // the current tracee is only let go of once the new one is attached to
+                let tracee = Tracee::attach(Pid::from_raw(pid))?;
+                let exited = tracee.wait_for_signal()?;
+                if let Err(e) = self.quit() {
+                    let _ = tracee.detach();
+                    return Err(e);
+                }
+                self.tracee = tracee;
+                self.attached = true;
+                // nothing carries over from the previous tracee
+                self.user_breakpoints.clear();
+                self.temp_breakpoints.clear();
+                self.debug_registers = DebugRegisters::default();
+                self.watchpoints.clear();
+                self.selected_frame = 0;
+                self.types = TypeTable::default();
+                self.unwinder = Unwinder::default();
+                self.skips.clear();
+                self.next_examine_addr = None;
+                self.history.clear();
+                if exited {
+                    return Ok(true);
+                }
+            }
+            "detach" => {
+                self.detach()?;
+                return Ok(true);
+            }
+            "q" | "quit" => {
+                self.quit()?;
+                return Ok(true);
+            }
+            cmd => return Err(anyhow!("Empty / Unknown command: {}", cmd)),
+        };
 
-        Ok(())
+        Ok(false)
     }
 }
 
 impl Debugger {
+    // Ends the session: a tracee that was attached to is left running as it was,
+    // one that was launched is killed
+    fn quit(&mut self) -> Result<()> {
+        if self.attached {
+            self.detach()
+        } else {
+            // it may already be gone
+            if signal::kill(self.tracee.pid, Signal::SIGKILL).is_ok() {
+                let _ = wait::waitpid(self.tracee.pid, None);
+            }
+            Ok(())
+        }
+    }
+
+    // Removes all the breakpoints from the tracee, restoring the original instructions,
+    // and lets it run on without being traced
+    fn detach(&mut self) -> Result<()> {
+        self.user_breakpoints.clear();
+        self.watchpoints.clear();
+        self.debug_registers.clear_all(self.tracee.pid)?;
+        for (_, mut bp) in self.tracee.breakpoints.drain() {
+            if bp.is_enabled() {
+                bp.disable()?;
+            }
+        }
+        self.tracee.detach()?;
+        eprintln!("Detached from process {}", self.tracee.pid);
+
+        Ok(())
+    }
+
     fn set_breakpoint_at(&mut self, addr: ptrace::AddressType) -> Result<()> {
         let mut bp = Breakpoint::new(self.tracee.pid, addr);
         bp.enable()?;
-        println!("bp: {:?}", bp);
-        self.breakpoints.insert(addr, bp);
+        self.tracee.breakpoints.insert(addr, bp);
+
+        Ok(())
+    }
+
+    fn add_user_breakpoint(
+        &mut self,
+        addr: ptrace::AddressType,
+        condition: Option<String>,
+    ) -> Result<()> {
+        let id = self.next_breakpoint_id;
+        self.next_breakpoint_id += 1;
+        let mut ub = UserBreakpoint::new(id, addr);
+        ub.condition = condition;
+        self.user_breakpoints.insert(id, ub);
+        self.sync_breakpoint_site(addr)?;
+        println!(
+            "Breakpoint {} at {:#x} {}",
+            id,
+            addr as u64,
+            self.tracee.describe_addr(addr as u64)?
+        );
+
+        Ok(())
+    }
+
+    fn add_hw_breakpoint(&mut self, addr: ptrace::AddressType) -> Result<()> {
+        let slot = self
+            .debug_registers
+            .set(self.tracee.pid, addr as u64, 1, Condition::Execute)?;
+        let id = self.next_breakpoint_id;
+        self.next_breakpoint_id += 1;
+        let mut ub = UserBreakpoint::new(id, addr);
+        ub.slot = Some(slot);
+        self.user_breakpoints.insert(id, ub);
+        println!(
+            "Hardware assisted breakpoint {} at {:#x} {}",
+            id,
+            addr as u64,
+            self.tracee.describe_addr(addr as u64)?
+        );
+
+        Ok(())
+    }
+
+    fn add_watchpoint(&mut self, addr: u64, len: usize, kind: WatchKind) -> Result<()> {
+        let cond = match kind {
+            WatchKind::Write => Condition::Write,
+            WatchKind::Read | WatchKind::Access => Condition::ReadWrite,
+        };
+        // read before taking a debug register, which an unreadable address would leave armed
+        let old_value = self.tracee.read_bytes(addr, len)?;
+        let slot = if debug_registers::can_watch(addr, len) && self.debug_registers.has_free_slot()
+        {
+            Some(self.debug_registers.set(self.tracee.pid, addr, len, cond)?)
+        } else if kind == WatchKind::Write {
+            // fall back to comparing the memory after every single step
+            None
+        } else {
+            return Err(anyhow!(
+                "Read and access watchpoints need a free debug register and 1, 2, 4 or 8 aligned bytes"
+            ));
+        };
+        let id = self.next_breakpoint_id;
+        self.next_breakpoint_id += 1;
+        let wp = Watchpoint {
+            id,
+            addr,
+            len,
+            kind,
+            slot,
+            enabled: true,
+            hit_count: 0,
+            old_value,
+        };
+        println!("{} {}: *{:#x} ({} bytes)", wp.title(), id, addr, len);
+        self.watchpoints.insert(id, wp);
+
+        Ok(())
+    }
+
+    fn get_user_breakpoint(&mut self, id: usize) -> Result<&mut UserBreakpoint> {
+        self.user_breakpoints
+            .get_mut(&id)
+            .ok_or(anyhow!("No breakpoint number {}", id))
+    }
+
+    fn delete_user_breakpoint(&mut self, id: usize) -> Result<()> {
+        if let Some(wp) = self.watchpoints.remove(&id) {
+            if let Some(slot) = wp.slot {
+                self.debug_registers.clear(self.tracee.pid, slot)?;
+            }
+            return Ok(());
+        }
+
+        let ub = self
+            .user_breakpoints
+            .remove(&id)
+            .ok_or(anyhow!("No breakpoint number {}", id))?;
+        if let Some(slot) = ub.slot {
+            self.debug_registers.clear(self.tracee.pid, slot)
+        } else {
+            self.sync_breakpoint_site(ub.addr)
+        }
+    }
+
+    fn set_user_breakpoint_enabled(&mut self, id: usize, enabled: bool) -> Result<()> {
+        if let Some(wp) = self.watchpoints.get_mut(&id) {
+            wp.enabled = enabled;
+            if let Some(slot) = wp.slot {
+                self.debug_registers
+                    .set_enabled(self.tracee.pid, slot, enabled)?;
+            } else if enabled {
+                // the value might have changed while the watchpoint was disabled
+                wp.old_value = self.tracee.read_bytes(wp.addr, wp.len)?;
+            }
+            return Ok(());
+        }
+
+        let ub = self.get_user_breakpoint(id)?;
+        ub.enabled = enabled;
+        let (addr, slot) = (ub.addr, ub.slot);
+        if let Some(slot) = slot {
+            self.debug_registers
+                .set_enabled(self.tracee.pid, slot, enabled)
+        } else {
+            self.sync_breakpoint_site(addr)
+        }
+    }
+
+    // Makes sure that the int3 at the address is present only if some enabled user breakpoint needs it
+    fn sync_breakpoint_site(&mut self, addr: ptrace::AddressType) -> Result<()> {
+        let needed = self
+            .user_breakpoints
+            .values()
+            .any(|ub| ub.addr == addr && ub.enabled && ub.slot.is_none());
+
+        if needed {
+            match self.tracee.breakpoints.get_mut(&addr) {
+                Some(bp) if !bp.is_enabled() => bp.enable()?,
+                Some(_) => {}
+                None => self.set_breakpoint_at(addr)?,
+            }
+        } else if let Some(mut bp) = self.tracee.breakpoints.remove(&addr)
+            && bp.is_enabled()
+        {
+            bp.disable()?;
+        }
+
+        Ok(())
+    }
+
+    // Decides whether the tracee, which has just stopped, should stay stopped.
+    // Hitting a user breakpoint whose condition doesn't hold or that still has hits to ignore,
+    // or a read watchpoint by writing to it, are the only cases in which it shouldn't.
+    fn should_stop(&mut self) -> Result<bool> {
+        let sig_info = ptrace::getsiginfo(self.tracee.pid)?;
+        if sig_info.si_signo == Signal::SIGTRAP as i32 && sig_info.si_code == TRAP_HWBKPT {
+            return self.should_stop_at_debug_trap();
+        }
+
+        let pc = registers::get_reg_value(self.tracee.pid, registers::Register::Rip)?;
+        if !self.is_breakpoint_site(pc) {
+            return Ok(true);
+        }
+        self.should_stop_at_breakpoint(pc)
+    }
+
+    fn is_breakpoint_site(&self, pc: u64) -> bool {
+        self.tracee
+            .breakpoints
+            .get(&(pc as ptrace::AddressType))
+            .is_some_and(|bp| bp.is_enabled())
+    }
+
+    fn should_stop_at_breakpoint(&mut self, pc: u64) -> Result<bool> {
+        let addr = pc as ptrace::AddressType;
+        let mut stop = self.temp_breakpoints.contains(&addr);
+        let ids = self
+            .user_breakpoints
+            .values()
+            .filter(|ub| ub.addr == addr && ub.enabled && ub.slot.is_none())
+            .map(|ub| ub.id)
+            .collect::<Vec<_>>();
+        if ids.is_empty() {
+            return Ok(true);
+        }
+
+        for id in ids {
+            stop |= self.check_user_breakpoint_hit(id, pc)?;
+        }
+
+        Ok(stop)
+    }
+
+    fn should_stop_at_debug_trap(&mut self) -> Result<bool> {
+        let pc = registers::get_reg_value(self.tracee.pid, registers::Register::Rip)?;
+        let slots = self.debug_registers.take_hits(self.tracee.pid)?;
+        // stop on traps that can't be attributed to any of our debug registers
+        let mut stop = slots.is_empty();
+
+        for slot in slots {
+            let ub = self
+                .user_breakpoints
+                .values()
+                .find(|ub| ub.slot == Some(slot) && ub.enabled);
+            let wp = self
+                .watchpoints
+                .values()
+                .find(|wp| wp.slot == Some(slot) && wp.enabled);
+            match (ub.map(|ub| ub.id), wp.map(|wp| wp.id)) {
+                (Some(id), _) => stop |= self.check_user_breakpoint_hit(id, pc)?,
+                (_, Some(id)) => stop |= self.check_watchpoint_hit(id, pc)?,
+                _ => {}
+            }
+        }
+
+        Ok(stop)
+    }
+
+    // Records a hit of the user breakpoint and returns whether it should stop the tracee
+    fn check_user_breakpoint_hit(&mut self, id: usize, pc: u64) -> Result<bool> {
+        if let Some(cond) = self.user_breakpoints[&id].condition.clone() {
+            let res = self.with_expr_context(|ctx| {
+                let value = ctx.evaluate(&cond)?;
+                ctx.is_true(&value)
+            });
+            match res {
+                Ok(true) => {}
+                Ok(false) => return Ok(false),
+                Err(e) => {
+                    eprintln!("Error in condition of breakpoint {}: {}", id, e);
+                    return Ok(true);
+                }
+            }
+        }
+
+        let ub = self.user_breakpoints.get_mut(&id).unwrap();
+        ub.hit_count += 1;
+        if ub.ignore_count > 0 {
+            ub.ignore_count -= 1;
+            return Ok(false);
+        }
+
+        println!(
+            "Breakpoint {}, {:#x} {}",
+            id,
+            pc,
+            self.tracee.describe_addr(pc)?
+        );
+        Ok(true)
+    }
+
+    // Reports the access to the watched memory and returns whether it should stop the tracee
+    fn check_watchpoint_hit(&mut self, id: usize, pc: u64) -> Result<bool> {
+        let wp = self.watchpoints.get_mut(&id).unwrap();
+        let new_value = self.tracee.read_bytes(wp.addr, wp.len)?;
+        let old_value = std::mem::replace(&mut wp.old_value, new_value.clone());
+        // x86 traps on writes as well as reads for read watchpoints,
+        // so the ones that changed the value are left out
+        if wp.kind == WatchKind::Read && old_value != new_value {
+            return Ok(false);
+        }
+        wp.hit_count += 1;
+
+        println!("\n{} {}: *{:#x}\n", wp.title(), id, wp.addr);
+        if old_value != new_value {
+            println!("Old value = {}", watchpoint::format_value(&old_value));
+            println!("New value = {}", watchpoint::format_value(&new_value));
+        } else {
+            println!("Value = {}", watchpoint::format_value(&new_value));
+        }
+        println!("{:#x} {}", pc, self.tracee.describe_addr(pc)?);
+        Ok(true)
+    }
+
+    fn print_breakpoints(&self) -> Result<()> {
+        if self.user_breakpoints.is_empty() && self.watchpoints.is_empty() {
+            println!("No breakpoints or watchpoints");
+            return Ok(());
+        }
+
+        println!(
+            "{:<4} {:<16} {:<8} {:<18} What",
+            "Num", "Type", "Enabled", "Address"
+        );
+        let mut ids = self
+            .user_breakpoints
+            .keys()
+            .chain(self.watchpoints.keys())
+            .copied()
+            .collect::<Vec<_>>();
+        ids.sort();
+
+        for id in ids {
+            let (kind, enabled, addr, what, hit_count) =
+                if let Some(ub) = self.user_breakpoints.get(&id) {
+                    let kind = if ub.slot.is_some() {
+                        "hw breakpoint"
+                    } else {
+                        "breakpoint"
+                    };
+                    let what = self.tracee.describe_addr(ub.addr as u64)?;
+                    (kind, ub.enabled, ub.addr as u64, what, ub.hit_count)
+                } else {
+                    let wp = &self.watchpoints[&id];
+                    let what = format!("*{:#x} ({} bytes)", wp.addr, wp.len);
+                    (wp.name(), wp.enabled, wp.addr, what, wp.hit_count)
+                };
+
+            println!(
+                "{:<4} {:<16} {:<8} {:<#18x} {}",
+                id,
+                kind,
+                if enabled { "y" } else { "n" },
+                addr,
+                what
+            );
+            if let Some(ub) = self.user_breakpoints.get(&id) {
+                if let Some(ref cond) = ub.condition {
+                    println!("\tstop only if {}", cond);
+                }
+                if ub.ignore_count > 0 {
+                    println!(
+                        "\twill ignore next {} crossing{} of breakpoint",
+                        ub.ignore_count,
+                        if ub.ignore_count == 1 { "" } else { "s" }
+                    );
+                }
+            }
+            if hit_count > 0 {
+                println!(
+                    "\tbreakpoint already hit {} time{}",
+                    hit_count,
+                    if hit_count == 1 { "" } else { "s" }
+                );
+            }
+        }
 
         Ok(())
     }
 
+    // Adds a skip for the pattern, or for the function / file of the selected frame if there is none
+    fn add_skip(&mut self, kind: SkipKind, pattern: &str) -> Result<()> {
+        let pattern = if !pattern.is_empty() {
+            pattern.to_owned()
+        } else {
+            let pc = self.get_frame_lookup_pc()?;
+            match kind {
+                SkipKind::Function => {
+                    self.tracee
+                        .get_func_range_at(pc)?
+                        .ok_or(anyhow!(
+                            "No function found containing current program point"
+                        ))?
+                        .0
+                }
+                SkipKind::File => self
+                    .tracee
+                    .get_line_entry_at(pc)?
+                    .ok_or(anyhow!("No default file now."))?
+                    .path
+                    .to_string_lossy()
+                    .into_owned(),
+            }
+        };
+
+        match kind {
+            SkipKind::Function => println!("Function {} will be skipped when stepping.", pattern),
+            SkipKind::File => println!("File {} will be skipped when stepping.", pattern),
+        }
+        self.skips.push(Skip {
+            id: self.next_skip_id,
+            kind,
+            pattern,
+        });
+        self.next_skip_id += 1;
+        Ok(())
+    }
+
+    fn print_skips(&self) {
+        if self.skips.is_empty() {
+            println!("Not skipping any files or functions.");
+            return;
+        }
+        println!("{:<4} {:<8} What", "Num", "Type");
+        for skip in &self.skips {
+            let kind = match skip.kind {
+                SkipKind::File => "file",
+                SkipKind::Function => "function",
+            };
+            println!("{:<4} {:<8} {}", skip.id, kind, skip.pattern);
+        }
+    }
+
     fn set_temp_breakpoint_at(
         &mut self,
         addr: ptrace::AddressType,
     ) -> Result<BreakpointLaterAction> {
-        let la = if let Some(bp) = self.breakpoints.get_mut(&addr) {
+        let la = if let Some(bp) = self.tracee.breakpoints.get_mut(&addr) {
             if bp.is_enabled() {
                 BreakpointLaterAction::Nothing
             } else {
@@ -127,21 +929,18 @@ impl Debugger {
             self.set_breakpoint_at(addr)?;
             BreakpointLaterAction::Delete
         };
+        self.temp_breakpoints.insert(addr);
         Ok(la)
     }
 
     fn step_over_breakpoint(&mut self) -> Result<bool> {
         let mut bp_present = false;
-        // let pc = registers::get_reg_value(self.pid, registers::Register::Rip)? - 1;
         let pc = registers::get_reg_value(self.tracee.pid, registers::Register::Rip)?;
-        if let Some(bp) = self.breakpoints.get_mut(&(pc as ptrace::AddressType)) && bp.is_enabled() {
-            eprintln!("step over breakpoint");
-            bp.disable()?;
-            // registers::set_reg_value(self.pid, registers::Register::Rip, pc)?;
+        if self.is_breakpoint_site(pc) {
+            let addr = pc as ptrace::AddressType;
+            self.tracee.breakpoints.get_mut(&addr).unwrap().disable()?;
             self.tracee.single_step_instr()?;
-            // ptrace::step(self.tracee.pid, None)?;
-            // self.tracee.wait_for_signal()?;
-            bp.enable()?;
+            self.tracee.breakpoints.get_mut(&addr).unwrap().enable()?;
 
             bp_present = true;
         }
@@ -150,6 +949,7 @@ impl Debugger {
     }
 
     fn single_step_instr_with_breakpoint_check(&mut self) -> Result<()> {
+        self.selected_frame = 0;
         if !self.step_over_breakpoint()? {
             self.tracee.single_step_instr()?;
         }
@@ -157,86 +957,644 @@ impl Debugger {
         Ok(())
     }
 
+    // Executes a single instruction, except that a call runs until it returns.
+    // The return value specifies if the tracee has exited, like for `continue_execution`.
+    fn step_over_instr(&mut self) -> Result<bool> {
+        let pc = registers::get_reg_value(self.tracee.pid, registers::Register::Rip)?;
+        let bytes = self.tracee.read_bytes(pc, disasm::MAX_INSTRUCTION_LEN)?;
+        let inst = disasm::decode(&bytes, pc, 1)
+            .pop()
+            .ok_or(anyhow!("Cannot decode the instruction at {:#x}", pc))?;
+        if !inst.is_call {
+            self.single_step_instr_with_breakpoint_check()?;
+            return Ok(false);
+        }
+
+        let sp = registers::get_reg_value(self.tracee.pid, registers::Register::Rsp)?;
+        self.run_until_return(inst.addr + inst.bytes.len() as u64, sp)
+    }
+
+    // Runs until the call returns to `ret_addr`, with the stack pointer back to `sp`.
+    // A recursive call returns to the same address in a deeper frame first, where the stack pointer is below it.
+    // Anything else stopping the tracee on the way stops it there.
+    // The return value specifies if the tracee has exited, like for `continue_execution`.
+    fn run_until_return(&mut self, ret_addr: u64, sp: u64) -> Result<bool> {
+        let la = self.set_temp_breakpoint_at(ret_addr as ptrace::AddressType)?;
+        loop {
+            if self.continue_execution()? {
+                return Ok(true);
+            }
+            let pc = registers::get_reg_value(self.tracee.pid, registers::Register::Rip)?;
+            let cur_sp = registers::get_reg_value(self.tracee.pid, registers::Register::Rsp)?;
+            if pc != ret_addr || cur_sp >= sp {
+                break;
+            }
+        }
+        self.reverse_breakpoint(ret_addr as ptrace::AddressType, la)?;
+
+        Ok(false)
+    }
+
     // The return value specifies if the tracee has exited or not
     // true => tracee has exited
     // false => tracee is still alive
     fn continue_execution(&mut self) -> Result<bool> {
-        self.step_over_breakpoint()?;
-        ptrace::cont(self.tracee.pid, None)?;
-        self.tracee.wait_for_signal()
+        self.selected_frame = 0;
+        if self
+            .watchpoints
+            .values()
+            .any(|wp| wp.enabled && wp.slot.is_none())
+        {
+            return self.continue_with_software_watchpoints();
+        }
+
+        loop {
+            self.step_over_breakpoint()?;
+            ptrace::cont(self.tracee.pid, None)?;
+            if self.tracee.wait_for_signal()? {
+                return Ok(true);
+            }
+            if self.should_stop()? {
+                return Ok(false);
+            }
+        }
     }
 
-    fn step_in(&mut self) -> Result<()> {
-        let start_line_entry = self.tracee.get_line_entry()?;
-        while self.tracee.get_line_entry()? == start_line_entry {
-            self.single_step_instr_with_breakpoint_check()?;
+    // Single steps the tracee, comparing the memory watched by software watchpoints after every instruction.
+    // Breakpoints and hardware watchpoints are still honoured along the way.
+    fn continue_with_software_watchpoints(&mut self) -> Result<bool> {
+        loop {
+            let pc = registers::get_reg_value(self.tracee.pid, registers::Register::Rip)?;
+            let bp_present = self.is_breakpoint_site(pc);
+            if bp_present {
+                self.tracee
+                    .breakpoints
+                    .get_mut(&(pc as ptrace::AddressType))
+                    .unwrap()
+                    .disable()?;
+            }
+            ptrace::step(self.tracee.pid, None)?;
+            if self.tracee.wait_for_signal()? {
+                return Ok(true);
+            }
+            if bp_present {
+                self.tracee
+                    .breakpoints
+                    .get_mut(&(pc as ptrace::AddressType))
+                    .unwrap()
+                    .enable()?;
+            }
+
+            if self.check_software_watchpoints(pc)? {
+                return Ok(false);
+            }
+
+            let sig_info = ptrace::getsiginfo(self.tracee.pid)?;
+            if sig_info.si_signo != Signal::SIGTRAP as i32 {
+                return Ok(false);
+            }
+            if sig_info.si_code == TRAP_HWBKPT {
+                if self.should_stop_at_debug_trap()? {
+                    return Ok(false);
+                }
+                continue;
+            }
+
+            let new_pc = registers::get_reg_value(self.tracee.pid, registers::Register::Rip)?;
+            if self.is_breakpoint_site(new_pc) && self.should_stop_at_breakpoint(new_pc)? {
+                return Ok(false);
+            }
+        }
+    }
+
+    // Compares the memory watched by the software watchpoints with the values seen last,
+    // `pc` being the address of the instruction that was just executed.
+    // Returns whether any of them changed.
+    fn check_software_watchpoints(&mut self, pc: u64) -> Result<bool> {
+        let mut changed = false;
+        for wp in self.watchpoints.values_mut() {
+            if !wp.enabled || wp.slot.is_some() {
+                continue;
+            }
+
+            let new_value = self.tracee.read_bytes(wp.addr, wp.len)?;
+            if new_value == wp.old_value {
+                continue;
+            }
+            let old_value = std::mem::replace(&mut wp.old_value, new_value);
+            wp.hit_count += 1;
+            changed = true;
+
+            println!("\n{} {}: *{:#x}\n", wp.title(), wp.id, wp.addr);
+            println!("Old value = {}", watchpoint::format_value(&old_value));
+            println!("New value = {}", watchpoint::format_value(&wp.old_value));
+            println!(
+                "changed by the instruction at {:#x} {}",
+                pc,
+                self.tracee.describe_addr(pc)?
+            );
+        }
+
+        Ok(changed)
+    }
+
+    // Prints the frames of the call stack, innermost first, as far as the stack can be unwound
+    fn print_backtrace(&mut self) -> Result<()> {
+        let frames = self.unwinder.backtrace(self.tracee.pid)?;
+        for (i, frame) in frames.iter().enumerate() {
+            println!(
+                "#{:<3} {:#018x} {}",
+                i,
+                frame.pc,
+                self.describe_frame(i, frame)?
+            );
         }
         Ok(())
     }
 
-    fn step_out(&mut self) -> Result<()> {
-        let fp = registers::get_reg_value(self.tracee.pid, registers::Register::Rbp)?;
-        let ret_addr = ptrace::read(self.tracee.pid, (fp + 8) as ptrace::AddressType)?;
+    // Makes the frame at `index` of the backtrace the selected one and prints where it is
+    fn select_frame(&mut self, frames: &[Frame], index: usize) -> Result<()> {
+        let frame = frames
+            .get(index)
+            .ok_or(anyhow!("No frame at level {}", index))?;
+        self.selected_frame = index;
+        println!(
+            "#{:<3} {:#018x} {}",
+            index,
+            frame.pc,
+            self.describe_frame(index, frame)?
+        );
+        self.tracee.print_source_at(frame.lookup_pc(index))
+    }
 
-        let la = self.set_temp_breakpoint_at(ret_addr as ptrace::AddressType)?;
+    fn get_selected_frame(&mut self) -> Result<Frame> {
+        let frames = self.unwinder.backtrace(self.tracee.pid)?;
+        frames
+            .into_iter()
+            .nth(self.selected_frame)
+            .ok_or(anyhow!("No frame at level {}", self.selected_frame))
+    }
+
+    // Reads the register as it was in the selected frame.
+    // The innermost frame has all of them, the callers only the ones the unwinder could recover.
+    fn get_frame_reg_value(&mut self, reg: registers::Register) -> Result<Option<u64>> {
+        if self.selected_frame == 0 {
+            return registers::get_reg_value(self.tracee.pid, reg).map(Some);
+        }
+        Ok(self.get_selected_frame()?.get_reg(reg))
+    }
 
-        self.continue_execution()?;
+    // The address to look up the source / function of the selected frame with
+    fn get_frame_lookup_pc(&mut self) -> Result<u64> {
+        if self.selected_frame == 0 {
+            return registers::get_reg_value(self.tracee.pid, registers::Register::Rip);
+        }
+        Ok(self.get_selected_frame()?.lookup_pc(self.selected_frame))
+    }
 
-        self.reverse_breakpoint(ret_addr as ptrace::AddressType, la)?;
+    // Prints the local variables, or the arguments, of the selected frame
+    fn print_frame_variables(&mut self, args: bool) -> Result<()> {
+        let frame = self.get_selected_frame()?;
+        let vars = variable::get_frame_variables(
+            &self.tracee,
+            &mut self.types,
+            &frame,
+            self.selected_frame,
+        )?;
+        let vars = vars
+            .iter()
+            .filter(|var| var.is_param == args)
+            .collect::<Vec<_>>();
+        if vars.is_empty() {
+            println!("{}", if args { "No arguments." } else { "No locals." });
+        }
+        for var in vars {
+            println!("{} = {}", var.name, var.format(&self.tracee, &self.types)?);
+        }
+        Ok(())
+    }
+
+    // Runs `f` with the expressions evaluated in the selected frame
+    fn with_expr_context<T>(
+        &mut self,
+        f: impl FnOnce(&mut expr::Context) -> Result<T>,
+    ) -> Result<T> {
+        let mut ctx = expr::Context::new(
+            Some(&mut self.tracee),
+            &mut self.types,
+            &mut self.unwinder,
+            &self.history,
+            self.selected_frame,
+        );
+        f(&mut ctx)
+    }
+
+    // Evaluates the expression as an address or a raw integer, e.g. `&counter` or `$rsp + 8`
+    fn evaluate_u64(&mut self, expr: &str) -> Result<u64> {
+        self.with_expr_context(|ctx| {
+            let value = ctx.evaluate(expr)?;
+            ctx.get_u64(&value)
+        })
+    }
+
+    // The address of the location, `*<expr>` being evaluated in the selected frame
+    fn resolve_location(&mut self, loc: &Location) -> Result<u64> {
+        match loc {
+            Location::Address(expr) => self.evaluate_u64(expr),
+            // a line of the file the selected frame is in, or of the one with `main` before it gets there
+            Location::Line(file, line) if file.is_empty() => {
+                let pc = self.get_frame_lookup_pc()?;
+                let le = match self.tracee.get_line_entry_at(pc)? {
+                    Some(le) => Some(le),
+                    None => {
+                        let main = Location::Function("main".to_owned());
+                        match self.tracee.resolve_location(&main) {
+                            Ok(addr) => self.tracee.get_line_entry_at(addr)?,
+                            Err(_) => None,
+                        }
+                    }
+                }
+                .ok_or(anyhow!("No default source file"))?;
+                let loc = Location::Line(le.path.to_string_lossy().into_owned(), *line);
+                self.tracee.resolve_location(&loc)
+            }
+            _ => self.tracee.resolve_location(loc),
+        }
+    }
+
+    // Disassembles the function containing the address, by default the one of the selected frame,
+    // or the range given as `start,end` or `start,+length`
+    fn disassemble(&mut self, arg: &str, mods: disasm::Modifiers) -> Result<()> {
+        let (range, func) = if let Some((start, end)) = arg.split_once(',') {
+            let start = self.evaluate_u64(start)?;
+            let end = match end.trim().strip_prefix('+') {
+                Some(len) => start
+                    .checked_add(self.evaluate_u64(len)?)
+                    .ok_or(anyhow!("Invalid range"))?,
+                None => self.evaluate_u64(end)?,
+            };
+            if end <= start {
+                return Err(anyhow!("Invalid range"));
+            }
+            if end - start > disasm::MAX_BYTES as u64 {
+                return Err(anyhow!(
+                    "At most {} bytes can be disassembled at once",
+                    disasm::MAX_BYTES
+                ));
+            }
+            (start..end, None)
+        } else {
+            let addr = if arg.is_empty() {
+                self.get_frame_lookup_pc()?
+            } else {
+                self.evaluate_u64(arg)?
+            };
+            let (name, range) = self
+                .tracee
+                .get_func_range_at(addr)?
+                .ok_or(anyhow!("No function contains specified address."))?;
+            (range, Some(name))
+        };
 
+        let pc = self.get_selected_frame()?.pc;
+        match func {
+            Some(ref name) => println!("Dump of assembler code for function {}:", name),
+            None => println!(
+                "Dump of assembler code from {:#x} to {:#x}:",
+                range.start, range.end
+            ),
+        }
+        let func_start = func.is_some().then_some(range.start);
+        disasm::print_range(
+            &self.tracee,
+            &mut self.unwinder,
+            range,
+            pc,
+            func_start,
+            mods,
+        )?;
+        println!("End of assembler dump.");
         Ok(())
     }
 
-    fn step_over(&mut self) -> Result<()> {
-        println!("start of step over");
+    // Returns the type of that name, or else the type of the expression,
+    // along with whether the argument named a type
+    fn resolve_type_or_expression(&mut self, arg: &str) -> Result<(TypeId, bool)> {
+        self.with_expr_context(|ctx| {
+            if let Ok(ty) = ctx.lookup_type(arg) {
+                return Ok((ty, true));
+            }
+            Ok((ctx.evaluate(arg)?.ty, false))
+        })
+    }
+
+    fn describe_frame(&mut self, index: usize, frame: &Frame) -> Result<String> {
+        let lookup_pc = frame.lookup_pc(index);
+        // only the executable has debug info loaded
+        let desc = if self.tracee.is_in_executable(lookup_pc) {
+            self.tracee.describe_addr(lookup_pc)?
+        } else {
+            String::new()
+        };
+        if !desc.is_empty() {
+            return Ok(desc);
+        }
+        Ok(self
+            .unwinder
+            .symbolize(self.tracee.pid, lookup_pc)?
+            .unwrap_or_default())
+    }
+
+    // Steps until another line is reached, going into the functions called on the way.
+    // Functions without line info, like the PLT stubs and the ones of libc, and skipped ones
+    // are run until they return instead of being stepped through.
+    // The return value specifies if the tracee has exited, like for `continue_execution`.
+    fn step_in(&mut self) -> Result<bool> {
+        let start_line = self.tracee.get_line_entry()?.map(|le| (le.path, le.line));
+        loop {
+            let pc = registers::get_reg_value(self.tracee.pid, registers::Register::Rip)?;
+            let bytes = self.tracee.read_bytes(pc, disasm::MAX_INSTRUCTION_LEN)?;
+            let mut called = disasm::decode(&bytes, pc, 1)
+                .first()
+                .is_some_and(|inst| inst.is_call);
+            self.single_step_instr_with_breakpoint_check()?;
+
+            loop {
+                let pc = registers::get_reg_value(self.tracee.pid, registers::Register::Rip)?;
+                if self.is_steppable(pc)? {
+                    break;
+                }
+                let (ret_addr, sp) = if called {
+                    // right after the call, the return address is on top of the stack
+                    let sp = registers::get_reg_value(self.tracee.pid, registers::Register::Rsp)?;
+                    (self.tracee.read_mem(sp)? as u64, sp + 8)
+                } else {
+                    // returned or jumped to code to pass over, which is left in its turn
+                    let frames = self.unwinder.backtrace(self.tracee.pid)?;
+                    match (frames.get(1), frames[0].cfa) {
+                        (Some(caller), Some(cfa)) => (caller.pc, cfa),
+                        _ => return self.continue_execution(),
+                    }
+                };
+                called = false;
+                if self.run_until_return(ret_addr, sp)? {
+                    return Ok(true);
+                }
+                if registers::get_reg_value(self.tracee.pid, registers::Register::Rip)? != ret_addr
+                {
+                    // stopped on the way
+                    return Ok(false);
+                }
+            }
+
+            let line = self.tracee.get_line_entry()?.map(|le| (le.path, le.line));
+            if line != start_line {
+                return Ok(false);
+            }
+        }
+    }
+
+    // Whether `step` can stop in the code at the address: it needs line info and not to be skipped
+    fn is_steppable(&mut self, pc: u64) -> Result<bool> {
+        let Some(le) = self.tracee.get_line_entry_at(pc)? else {
+            return Ok(false);
+        };
+        if le.line == 0 {
+            return Ok(false);
+        }
+        if self.skips.is_empty() {
+            return Ok(true);
+        }
+        let func = self.tracee.get_func_range_at(pc)?.map(|(name, _)| name);
+        Ok(!self
+            .skips
+            .iter()
+            .any(|skip| skip.matches(func.as_deref(), &le.path)))
+    }
+
+    // Runs until the selected frame returns to its caller, and shows the value it returned.
+    // The value is recorded in the history for expressions to refer to.
+    // The return value specifies if the tracee has exited, like for `continue_execution`.
+    fn step_out(&mut self) -> Result<bool> {
+        let frames = self.unwinder.backtrace(self.tracee.pid)?;
+        let index = self.selected_frame;
+        let frame = frames
+            .get(index)
+            .ok_or(anyhow!("No frame at level {}", index))?;
+        let ret_addr = frames
+            .get(index + 1)
+            .ok_or(anyhow!("\"finish\" not meaningful in the outermost frame."))?
+            .pc;
+        let ret_ty = self.get_return_type(frame.lookup_pc(index))?;
+        // the return address is popped when returning, leaving the stack pointer at the CFA
+        let sp = match frame.cfa {
+            Some(cfa) => cfa,
+            None => frame
+                .get_reg(registers::Register::Rsp)
+                .ok_or(anyhow!("Cannot find the stack pointer of frame {}", index))?,
+        };
+
+        if self.run_until_return(ret_addr, sp)? {
+            return Ok(true);
+        }
+        let pc = registers::get_reg_value(self.tracee.pid, registers::Register::Rip)?;
+        // stopped on the way by a breakpoint or a signal
+        if pc != ret_addr {
+            return Ok(false);
+        }
+
+        if let Some(ty) = ret_ty {
+            let bytes = abi::get_return_value(&self.tracee, &self.types, ty)?;
+            let value = Value {
+                ty,
+                storage: Storage::Computed(bytes),
+            };
+            self.history.push(value.clone());
+            let value = self.with_expr_context(|ctx| ctx.format(&value))?;
+            println!("Value returned is ${} = {}", self.history.len(), value);
+        }
+
+        Ok(false)
+    }
+
+    // Pops the selected frame and the ones below it without running the rest of their code,
+    // leaving the tracee in the caller right after the call, with the value as the return value
+    fn force_return(&mut self, expr: &str) -> Result<()> {
+        let frames = self.unwinder.backtrace(self.tracee.pid)?;
+        let index = self.selected_frame;
+        let frame = frames
+            .get(index)
+            .ok_or(anyhow!("No frame at level {}", index))?;
+        let caller = frames
+            .get(index + 1)
+            .ok_or(anyhow!("Can not force return from the outermost frame"))?;
+        let ret_ty = self.get_return_type(frame.lookup_pc(index))?;
+
+        if !expr.is_empty() {
+            let value = self.with_expr_context(|ctx| {
+                let value = ctx.evaluate(expr)?;
+                // converted to the return type like a C `return` statement does
+                let value = match ret_ty {
+                    Some(ty) => ctx.cast(value, ty)?,
+                    None => value,
+                };
+                Ok(Value {
+                    ty: value.ty,
+                    storage: Storage::Computed(ctx.read(&value)?),
+                })
+            })?;
+            let Storage::Computed(ref bytes) = value.storage else {
+                unreachable!("read above");
+            };
+            abi::set_return_value(self.tracee.pid, &self.types, value.ty, bytes)?;
+        }
+
+        // the caller's registers as the unwinder recovered them: the callee saved ones,
+        // and the stack pointer and pc as they are after `ret`
+        let mut regs = ptrace::getregs(self.tracee.pid)?;
+        for reg in [
+            registers::Register::Rbx,
+            registers::Register::Rbp,
+            registers::Register::R12,
+            registers::Register::R13,
+            registers::Register::R14,
+            registers::Register::R15,
+            registers::Register::Rsp,
+            registers::Register::Rip,
+        ] {
+            let value = caller
+                .get_reg(reg)
+                .ok_or(anyhow!("Value of {:?} is not available in the caller", reg))?;
+            *registers::get_mutable_reg(&mut regs, reg) = value;
+        }
+        ptrace::setregs(self.tracee.pid, regs)?;
+
+        let frames = self.unwinder.backtrace(self.tracee.pid)?;
+        self.select_frame(&frames, 0)
+    }
+
+    // The type the function at the address returns, if it is known and not `void`
+    fn get_return_type(&mut self, pc: u64) -> Result<Option<TypeId>> {
+        if !self.tracee.is_in_executable(pc) {
+            return Ok(None);
+        }
+        let dwarf = self.tracee.load_dwarf()?;
+        let offset_pc = self.tracee.offset_load_addr(pc);
+        let Some((unit, offset)) = dwarf::get_function_from_pc(&dwarf, offset_pc)? else {
+            return Ok(None);
+        };
+        let ty = self.types.load(&dwarf, &unit, offset)?;
+        match *self.types.get(self.types.strip(ty)) {
+            Type::Function { ret, .. } if ret != types::VOID => Ok(Some(ret)),
+            _ => Ok(None),
+        }
+    }
+
+    // Runs until another line of the current function is reached in the same invocation of it,
+    // or until it returns to its caller. Lines reached by recursive calls are passed over,
+    // their frames being below the current one.
+    // The return value specifies if the tracee has exited, like for `continue_execution`.
+    fn step_over(&mut self) -> Result<bool> {
+        self.run_to_next_line(false)
+    }
+
+    // Like `step_over`, but only stops at lines past the current one, to leave loops
+    fn run_until_next_line(&mut self) -> Result<bool> {
+        self.run_to_next_line(true)
+    }
+
+    fn run_to_next_line(&mut self, forward_only: bool) -> Result<bool> {
+        let pc = registers::get_reg_value(self.tracee.pid, registers::Register::Rip)?;
         let (unit, offset) = self
             .tracee
             .get_func()?
             .ok_or(anyhow!("Currently not in a function defined in the binary"))?;
-        let func = unit.entry(offset)?;
-        println!("found function");
-
-        let func_range = dwarf::get_die_addr_range(&func)?;
-        let func_start_line =
-            dwarf::get_line_entry_from_pc(&self.tracee.load_dwarf()?, func_range.start)?
-                .ok_or(anyhow!("Func start not found"))?;
-        let func_end_line =
-            dwarf::get_line_entry_from_pc(&self.tracee.load_dwarf()?, func_range.end)?
-                .ok_or(anyhow!("Func end not found"))?;
-        let start_line = self
-            .tracee
-            .get_line_entry()?
-            .ok_or(anyhow!("Start line not found"))?;
-        println!("found function lines:\nfunc_start_line: {:#?}\nfunc_end_line: {:#?}\nstart_line: {:#?}\n", func_start_line, func_end_line, start_line);
+        let dwarf = self.tracee.load_dwarf()?;
+        let offset_pc = self.tracee.offset_load_addr(pc);
+        let current = dwarf::get_line_entry_from_pc(&dwarf, offset_pc)?;
 
-        let lines = dwarf::get_lines_for_unit(&unit)?;
-        println!("lines: {:#?}", lines);
-        let mut addrs = (func_start_line.line..=func_end_line.line)
+        let addrs = dwarf::get_function_lines(&dwarf, &unit, &unit.entry(offset)?)?
             .into_iter()
-            .filter(|line_no| *line_no != start_line.line)
-            .filter_map(|ref line_no| {
-                println!("line_no: {}", line_no);
-                lines.get(line_no)
-                .map(|addr| {
-                    let addr = self.tracee.add_load_addr(*addr as u64) as ptrace::AddressType;
-                    self.set_temp_breakpoint_at(addr).map(|la| (addr, la))
-                })
+            .filter(|le| match current {
+                Some(ref cur) if cur.path == le.path && forward_only => le.line > cur.line,
+                Some(ref cur) if cur.path == le.path => le.line != cur.line,
+                _ => true,
             })
-            .collect::<Result<Vec<_>>>()?;
-        println!("addrs: {:#?}", addrs);
+            .map(|le| self.tracee.add_load_addr(le.addr))
+            .filter(|addr| *addr != pc)
+            .collect::<HashSet<_>>();
+
+        self.run_to_addrs(addrs, true)
+    }
 
-        let fp = registers::get_reg_value(self.tracee.pid, registers::Register::Rbp)?;
-        let ret_addr = ptrace::read(self.tracee.pid, (fp + 8) as ptrace::AddressType)?;
-        let rla = self.set_temp_breakpoint_at(ret_addr as ptrace::AddressType)?;
-        addrs.push((ret_addr as ptrace::AddressType, rla));
+    // Runs until one of the addresses is reached, or until the current function returns to its caller.
+    // With `same_frame`, the addresses only stop the tracee in the current invocation of the function,
+    // not in the ones of recursive calls.
+    // The return value specifies if the tracee has exited, like for `continue_execution`.
+    fn run_to_addrs(&mut self, mut addrs: HashSet<u64>, same_frame: bool) -> Result<bool> {
+        let frames = self.unwinder.backtrace(self.tracee.pid)?;
+        let cfa = frames[0].cfa;
+        let ret_addr = frames.get(1).map(|caller| caller.pc);
+        addrs.extend(ret_addr);
+
+        let mut las = Vec::new();
+        for addr in addrs {
+            let addr = addr as ptrace::AddressType;
+            las.push((addr, self.set_temp_breakpoint_at(addr)?));
+        }
 
-        self.continue_execution()?;
+        loop {
+            if self.continue_execution()? {
+                return Ok(true);
+            }
+            let pc = registers::get_reg_value(self.tracee.pid, registers::Register::Rip)?;
+            if !self.temp_breakpoints.contains(&(pc as ptrace::AddressType)) {
+                // stopped by a breakpoint, a watchpoint or a signal
+                break;
+            }
+            // back in the caller once the return address is popped, the stack pointer being the CFA again.
+            // The frames of recursive calls are below the current one.
+            let done = if Some(pc) == ret_addr {
+                let sp = registers::get_reg_value(self.tracee.pid, registers::Register::Rsp)?;
+                cfa.is_none_or(|cfa| sp >= cfa)
+            } else if same_frame {
+                let frames = self.unwinder.backtrace(self.tracee.pid)?;
+                match (cfa, frames[0].cfa) {
+                    (Some(cfa), Some(cur_cfa)) => cur_cfa >= cfa,
+                    _ => true,
+                }
+            } else {
+                true
+            };
+            if done {
+                break;
+            }
+        }
 
-        for (addr, la) in addrs {
+        for (addr, la) in las {
             self.reverse_breakpoint(addr, la)?;
         }
 
-        Ok(())
+        Ok(false)
+    }
+
+    // Resumes the execution at the address, which has to be in the current function
+    // as the frame stays the same
+    fn jump(&mut self, addr: u64) -> Result<bool> {
+        let pc = registers::get_reg_value(self.tracee.pid, registers::Register::Rip)?;
+        let in_func = self
+            .tracee
+            .get_func_range_at(pc)?
+            .is_some_and(|(_, range)| range.contains(&addr));
+        if !in_func {
+            return Err(anyhow!(
+                "Location {:#x} is not in the current function",
+                addr
+            ));
+        }
+        registers::set_reg_value(self.tracee.pid, registers::Register::Rip, addr)?;
+        println!("Continuing at {:#x}.", addr);
+        self.continue_execution()
     }
 
     fn reverse_breakpoint(
@@ -244,18 +1602,19 @@ impl Debugger {
         key: ptrace::AddressType,
         action: BreakpointLaterAction,
     ) -> Result<()> {
+        self.temp_breakpoints.remove(&key);
         match action {
             BreakpointLaterAction::Nothing => {}
             BreakpointLaterAction::Delete => {
-                let mut bp = self.breakpoints.remove(&key).unwrap();
+                let mut bp = self.tracee.breakpoints.remove(&key).unwrap();
                 bp.disable()?;
             }
             BreakpointLaterAction::Disable => {
-                let bp = self.breakpoints.get_mut(&key).unwrap();
+                let bp = self.tracee.breakpoints.get_mut(&key).unwrap();
                 bp.disable()?;
             }
             BreakpointLaterAction::Enable => {
-                let bp = self.breakpoints.get_mut(&key).unwrap();
+                let bp = self.tracee.breakpoints.get_mut(&key).unwrap();
                 bp.enable()?;
             }
         };
@@ -263,3 +1622,37 @@ impl Debugger {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser as _;
+
+    // A program that does nothing if let go of, stopped before it runs
+    fn launch() -> Debugger {
+        let args = Args::parse_from(["debugger", "/bin/true"]);
+        let debugger = Debugger::new(&args).unwrap();
+        assert!(!debugger.tracee.wait_for_signal().unwrap());
+        debugger
+    }
+
+    #[test]
+    #[ignore = "launches a tracee, which needs ptrace"]
+    fn unreadable_watchpoint_keeps_debug_registers_free() {
+        let mut debugger = launch();
+        assert!(debugger.add_watchpoint(0, 4, WatchKind::Write).is_err());
+        assert!(debugger.watchpoints.is_empty());
+
+        // all four debug registers are still there to take
+        let sp = registers::get_reg_value(debugger.tracee.pid, registers::Register::Rsp).unwrap();
+        for i in 0..4 {
+            debugger
+                .add_watchpoint((sp & !0x7) + 8 * i, 8, WatchKind::Write)
+                .unwrap();
+        }
+        let slots = debugger.watchpoints.values().filter(|wp| wp.slot.is_some());
+        assert_eq!(slots.count(), 4);
+
+        debugger.quit().unwrap();
+    }
+}
@@ -0,0 +1,270 @@
+use anyhow::{anyhow, Result};
+use gimli::{Dwarf, EndianSlice, RunTimeEndian};
+use iced_x86::{Decoder, DecoderOptions, Formatter, GasFormatter, Mnemonic, OpKind};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use crate::{dwarf, registers, tracee::Tracee, unwind::Unwinder, util};
+
+// Longest an x86-64 instruction can be
+pub const MAX_INSTRUCTION_LEN: usize = 15;
+
+// Most bytes decoded by one command, so that a mistyped count or range isn't read all at once
+pub const MAX_BYTES: usize = 64 * 1024;
+
+// An instruction decoded from the tracee's memory
+#[derive(Debug)]
+pub struct Instruction {
+    pub addr: u64,
+    pub bytes: Vec<u8>,
+    // in AT&T syntax, like GDB shows it by default
+    pub text: String,
+    // where a direct call or jump goes to
+    pub target: Option<u64>,
+    // the address a `%rip` relative operand refers to
+    pub rip_relative: Option<u64>,
+    pub is_call: bool,
+}
+
+// The `/rs` of `disassemble`: `raw` shows the bytes of the instructions,
+// `source` the source lines they were compiled from
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Modifiers {
+    pub raw: bool,
+    pub source: bool,
+}
+
+impl Modifiers {
+    pub fn parse(letters: &str) -> Result<Self> {
+        let mut mods = Self::default();
+        for c in letters.chars() {
+            match c {
+                'r' => mods.raw = true,
+                's' => mods.source = true,
+                _ => return Err(anyhow!("Invalid disassembly modifier: {}", c)),
+            }
+        }
+        Ok(mods)
+    }
+}
+
+// Decodes up to `max` instructions from the machine code found at `addr`.
+// Bytes that aren't a valid instruction are shown as `(bad)`.
+pub fn decode(bytes: &[u8], addr: u64, max: usize) -> Vec<Instruction> {
+    let mut decoder = Decoder::with_ip(64, bytes, addr, DecoderOptions::NONE);
+    let mut formatter = GasFormatter::new();
+    // formatted like GDB does
+    let options = formatter.options_mut();
+    options.set_first_operand_char_index(7);
+    options.set_uppercase_hex(false);
+    options.set_branch_leading_zeros(false);
+    options.set_rip_relative_addresses(true);
+
+    let mut insts = Vec::new();
+    let mut instr = iced_x86::Instruction::default();
+    while decoder.can_decode() && insts.len() < max {
+        let offset = decoder.position();
+        decoder.decode_out(&mut instr);
+        let mut text = String::new();
+        if instr.is_invalid() {
+            text.push_str("(bad)");
+        } else {
+            formatter.format(&instr, &mut text);
+        }
+        let target = (instr.op0_kind() == OpKind::NearBranch64).then(|| instr.near_branch64());
+        let rip_relative = (!instr.is_invalid() && instr.is_ip_rel_memory_operand())
+            .then(|| instr.ip_rel_memory_address());
+        insts.push(Instruction {
+            addr: instr.ip(),
+            bytes: bytes[offset..offset + instr.len()].to_vec(),
+            text,
+            target,
+            rip_relative,
+            is_call: instr.mnemonic() == Mnemonic::Call,
+        });
+    }
+    insts
+}
+
+// Prints the instructions in the range, `=>` marking the one at `pc`.
+// The addresses of a whole function are labelled with their offset from `func_start`.
+pub fn print_range(
+    tracee: &Tracee,
+    unwinder: &mut Unwinder,
+    range: Range<u64>,
+    pc: u64,
+    func_start: Option<u64>,
+    mods: Modifiers,
+) -> Result<()> {
+    // the last instruction may go past the end of the range
+    let len = range.end.saturating_sub(range.start) as usize;
+    let bytes = tracee
+        .read_bytes(range.start, len.saturating_add(MAX_INSTRUCTION_LEN))
+        .or_else(|_| tracee.read_bytes(range.start, len))?;
+    let insts = decode(&bytes, range.start, usize::MAX);
+
+    let mut source = if mods.source {
+        Some(SourceLines::new(tracee)?)
+    } else {
+        None
+    };
+
+    for inst in insts.iter().take_while(|inst| inst.addr < range.end) {
+        if let Some(ref mut source) = source {
+            source.print_lines_for(inst.addr)?;
+        }
+        let label = match func_start {
+            Some(start) => format!("<+{}>", inst.addr - start),
+            None => symbol_label(tracee, unwinder, inst.addr)?,
+        };
+        let addr = format!("{:#018x} {}", inst.addr, label);
+        print_instruction(tracee, unwinder, inst, addr.trim_end(), pc, mods.raw)?;
+    }
+    Ok(())
+}
+
+// Prints `count` instructions starting at `addr`, for `x/i`.
+// Returns the address following the last one.
+pub fn print_count(
+    tracee: &Tracee,
+    unwinder: &mut Unwinder,
+    addr: u64,
+    count: usize,
+) -> Result<u64> {
+    let pc = registers::get_reg_value(tracee.pid, registers::Register::Rip)?;
+    let len = count
+        .checked_mul(MAX_INSTRUCTION_LEN)
+        .filter(|&len| len <= MAX_BYTES)
+        .ok_or(anyhow!(
+            "At most {} instructions can be examined at once",
+            MAX_BYTES / MAX_INSTRUCTION_LEN
+        ))?;
+    // near the end of the readable memory, only the rest of the page is decoded
+    let page_len = 0x1000 - (addr & 0xfff) as usize;
+    let bytes = tracee
+        .read_bytes(addr, len)
+        .or_else(|_| tracee.read_bytes(addr, page_len.min(len)))?;
+
+    let mut next = addr;
+    for inst in decode(&bytes, addr, count) {
+        let label = format!(
+            "{:#x} {}",
+            inst.addr,
+            symbol_label(tracee, unwinder, inst.addr)?
+        );
+        print_instruction(tracee, unwinder, &inst, label.trim_end(), pc, false)?;
+        next = inst.addr + inst.bytes.len() as u64;
+    }
+    Ok(next)
+}
+
+// e.g. `=> 0x0000555555555143 <+10>:	call   0x555555555139 <sum>`
+fn print_instruction(
+    tracee: &Tracee,
+    unwinder: &mut Unwinder,
+    inst: &Instruction,
+    addr: &str,
+    pc: u64,
+    raw: bool,
+) -> Result<()> {
+    let marker = if inst.addr == pc { "=> " } else { "   " };
+    let bytes = if raw {
+        let hex = inst.bytes.iter().map(|b| format!("{:02x}", b));
+        format!("{}\t", hex.collect::<Vec<_>>().join(" "))
+    } else {
+        String::new()
+    };
+    let target = match (inst.target, inst.rip_relative) {
+        (Some(target), _) => format!(" {}", symbol_label(tracee, unwinder, target)?),
+        (None, Some(addr)) => format!(
+            "        # {:#x} {}",
+            addr,
+            symbol_label(tracee, unwinder, addr)?
+        ),
+        _ => String::new(),
+    };
+    println!(
+        "{}{}:\t{}{}{}",
+        marker,
+        addr,
+        bytes,
+        inst.text,
+        target.trim_end()
+    );
+    Ok(())
+}
+
+// `<name+offset>` of the symbol containing the address, if any
+fn symbol_label(tracee: &Tracee, unwinder: &mut Unwinder, addr: u64) -> Result<String> {
+    let label = match unwinder.symbol_at(tracee.pid, addr)? {
+        Some((name, 0)) => format!("<{}>", name),
+        Some((name, offset)) => format!("<{}+{}>", name, offset),
+        None => String::new(),
+    };
+    Ok(label)
+}
+
+// Shows the source lines before the instructions generated for them, for `disassemble /s`
+struct SourceLines<'a> {
+    tracee: &'a Tracee,
+    dwarf: Dwarf<EndianSlice<'a, RunTimeEndian>>,
+    // contents of the source files read so far
+    files: HashMap<PathBuf, Vec<String>>,
+    // the last line printed
+    last: Option<(PathBuf, usize)>,
+}
+
+impl<'a> SourceLines<'a> {
+    fn new(tracee: &'a Tracee) -> Result<Self> {
+        Ok(Self {
+            tracee,
+            dwarf: tracee.load_dwarf()?,
+            files: HashMap::new(),
+            last: None,
+        })
+    }
+
+    // Prints the line the instruction at the address belongs to, when it starts a new line.
+    // Going further down the same file also prints the lines in between, like GDB does.
+    fn print_lines_for(&mut self, addr: u64) -> Result<()> {
+        if !self.tracee.is_in_executable(addr) {
+            return Ok(());
+        }
+        let offset_addr = self.tracee.offset_load_addr(addr);
+        let Some(le) = dwarf::get_line_entry_from_pc(&self.dwarf, offset_addr)? else {
+            return Ok(());
+        };
+        if le.line == 0 {
+            return Ok(());
+        }
+
+        let first = match self.last {
+            Some((ref path, line)) if *path == le.path && line == le.line => return Ok(()),
+            Some((ref path, line)) if *path == le.path && line < le.line => {
+                println!();
+                line + 1
+            }
+            Some((ref path, _)) if *path == le.path => {
+                println!();
+                le.line
+            }
+            _ => {
+                println!("{}:", le.path.display());
+                le.line
+            }
+        };
+
+        let lines = self.files.entry(le.path.clone()).or_insert_with(|| {
+            util::get_file_lines(le.path.to_string_lossy())
+                .map(|lines| lines.map_while(|line| line.ok()).collect())
+                .unwrap_or_default()
+        });
+        for n in first..=le.line {
+            println!("{}\t{}", n, lines.get(n - 1).map_or("", String::as_str));
+        }
+
+        self.last = Some((le.path, le.line));
+        Ok(())
+    }
+}
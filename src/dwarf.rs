@@ -1,15 +1,16 @@
-use std::collections::HashMap;
 use std::path;
 use std::{ops::Range, path::PathBuf};
 
 use anyhow::{anyhow, Result};
 use gimli::{
-    read::{DebuggingInformationEntry, Unit, UnitOffset},
-    Dwarf, EndianSlice, RunTimeEndian,
+    read::{DebuggingInformationEntry, EntriesTreeNode, Unit, UnitOffset},
+    AttributeValue, Dwarf, EndianSlice, RunTimeEndian,
 };
-use nix::sys::ptrace;
 use object::{Object, ObjectSection};
 
+// A DIE, as the unit it is in and its offset there
+pub type DieRef<R> = (Unit<R>, UnitOffset<<R as gimli::Reader>::Offset>);
+
 // #[allow(unused)]
 // pub fn runner() {
 //     let path = "target/test";
@@ -56,7 +57,7 @@ pub(crate) fn load_dwarf<'o>(
 pub fn get_function_from_pc<R: gimli::Reader>(
     dwarf: &Dwarf<R>,
     pc: u64,
-) -> Result<Option<(Unit<R>, UnitOffset<<R as gimli::Reader>::Offset>)>> {
+) -> Result<Option<DieRef<R>>> {
     if let Some(unit) = get_compile_unit_for_pc(dwarf, pc)? {
         // let mut depth = 0;
 
@@ -64,7 +65,7 @@ pub fn get_function_from_pc<R: gimli::Reader>(
         while let Some((_delta_depth, entry)) = entries.next_dfs()? {
             // depth += delta_depth;
             if matches!(entry.tag(), gimli::DW_TAG_subprogram)
-                && get_die_addr_range(entry)?.contains(&pc)
+                && die_contains_pc(dwarf, &unit, entry, pc)?
             {
                 let offset = entry.offset().to_owned();
                 return Ok(Some((unit, offset)));
@@ -75,6 +76,77 @@ pub fn get_function_from_pc<R: gimli::Reader>(
     Ok(None)
 }
 
+pub fn get_die_name<R: gimli::Reader>(
+    dwarf: &Dwarf<R>,
+    unit: &Unit<R>,
+    entry: &DebuggingInformationEntry<R>,
+) -> Result<Option<String>> {
+    let Some(attr) = get_die_attr(unit, entry, gimli::DW_AT_name)? else {
+        return Ok(None);
+    };
+    let name = dwarf
+        .attr_string(unit, attr)?
+        .to_string_lossy()?
+        .into_owned();
+    Ok(Some(name))
+}
+
+// Looks up the attribute of the DIE, falling back to the DIE it is a concrete instance of.
+// The out of line copies of inlined functions and their variables only refer to the abstract DIE
+// holding their name and type, and definitions following a declaration only refer to it.
+pub fn get_die_attr<R: gimli::Reader>(
+    unit: &Unit<R>,
+    entry: &DebuggingInformationEntry<R>,
+    name: gimli::DwAt,
+) -> Result<Option<AttributeValue<R>>> {
+    if let Some(value) = entry.attr_value(name)? {
+        return Ok(Some(value));
+    }
+    for origin in [gimli::DW_AT_abstract_origin, gimli::DW_AT_specification] {
+        if let Some(AttributeValue::UnitRef(origin)) = entry.attr_value(origin)? {
+            return Ok(unit.entry(origin)?.attr_value(name)?);
+        }
+    }
+    Ok(None)
+}
+
+// Returns the variables and parameters of the function that are visible at `pc`.
+// The ones of the innermost lexical blocks come first, as they shadow the outer ones.
+pub fn get_variables_in_scope<R: gimli::Reader>(
+    dwarf: &Dwarf<R>,
+    unit: &Unit<R>,
+    func: UnitOffset<R::Offset>,
+    pc: u64,
+) -> Result<Vec<UnitOffset<R::Offset>>> {
+    let mut tree = unit.entries_tree(Some(func))?;
+    let mut vars = Vec::new();
+    collect_scope_variables(dwarf, unit, tree.root()?, pc, &mut vars)?;
+    Ok(vars)
+}
+
+fn collect_scope_variables<R: gimli::Reader>(
+    dwarf: &Dwarf<R>,
+    unit: &Unit<R>,
+    scope: EntriesTreeNode<R>,
+    pc: u64,
+    vars: &mut Vec<UnitOffset<R::Offset>>,
+) -> Result<()> {
+    let mut own = Vec::new();
+    let mut children = scope.children();
+    while let Some(child) = children.next()? {
+        let entry = child.entry();
+        match entry.tag() {
+            gimli::DW_TAG_variable | gimli::DW_TAG_formal_parameter => own.push(entry.offset()),
+            gimli::DW_TAG_lexical_block if die_contains_pc(dwarf, unit, entry, pc)? => {
+                collect_scope_variables(dwarf, unit, child, pc, vars)?
+            }
+            _ => {}
+        }
+    }
+    vars.extend(own);
+    Ok(())
+}
+
 pub fn get_line_entry_from_pc<R: gimli::Reader>(
     dwarf: &Dwarf<R>,
     pc: u64,
@@ -84,36 +156,24 @@ pub fn get_line_entry_from_pc<R: gimli::Reader>(
     if let Some(unit) = get_compile_unit_for_pc(dwarf, pc)?
     && let Some(program) = unit.line_program.clone()
     {
-        let comp_dir = if let Some(ref dir) = unit.comp_dir {
-            path::PathBuf::from(dir.to_string_lossy()?.into_owned())
-        } else {
-            path::PathBuf::new()
-        };
-
         let mut rows = program.rows();
-        while let Some((header, row)) = rows.next_row()? && row.address() < pc{
-            // Determine the path. Real applications should cache this for performance.
-            let mut path = path::PathBuf::new();
-            if let Some(file) = row.file(header) {
-                path = comp_dir.clone();
-
-                // The directory index 0 is defined to correspond to the compilation unit directory.
-                if file.directory_index() != 0 {
-                    if let Some(dir) = file.directory(header) {
-                        path.push(
-                            dwarf.attr_string(&unit, dir)?.to_string_lossy()?.as_ref(),
-                        );
-                    }
+        while let Some((header, row)) = rows.next_row()? {
+            // The rows of a sequence are sorted by address, but the sequences themselves aren't.
+            // The row covering the pc is the last one at or before it, in the sequence that ends after it.
+            if row.end_sequence() {
+                if value.is_some() && pc < row.address() {
+                    break;
                 }
-
-                path.push(
-                    dwarf
-                        .attr_string(&unit, file.path_name())?
-                        .to_string_lossy()?
-                        .as_ref(),
-                );
+                value = None;
+                continue;
+            }
+            if row.address() > pc {
+                continue;
             }
 
+            // Determine the path. Real applications should cache this for performance.
+            let path = get_row_path(dwarf, &unit, header, row)?;
+
             // Determine line/column. DWARF line/column is never 0, so we use that
             // but other applications may want to display this differently.
             let line = match row.line() {
@@ -137,6 +197,289 @@ pub fn get_line_entry_from_pc<R: gimli::Reader>(
     Ok(value)
 }
 
+// Finds the function with the given name and returns the address of its first instruction after the prologue,
+// so that a breakpoint placed there sees the frame already set up
+pub fn get_function_addr_by_name<R: gimli::Reader>(
+    dwarf: &Dwarf<R>,
+    name: &str,
+) -> Result<Option<u64>> {
+    let Some((unit, offset)) = find_function_by_name(dwarf, name)? else {
+        return Ok(None);
+    };
+    let range = get_die_entry_range(dwarf, &unit, &unit.entry(offset)?)?
+        .ok_or(anyhow!("Function {} has no addresses", name))?;
+    Ok(Some(get_prologue_end(&unit, range)?))
+}
+
+// Finds the DIE of the function with the given name that has code in the executable
+pub fn find_function_by_name<R: gimli::Reader>(
+    dwarf: &Dwarf<R>,
+    name: &str,
+) -> Result<Option<DieRef<R>>> {
+    let mut iter = dwarf.units();
+    while let Some(header) = iter.next()? {
+        let unit = dwarf.unit(header)?;
+        let mut entries = unit.entries();
+        while let Some((_, entry)) = entries.next_dfs()? {
+            // functions split into several parts only have `DW_AT_ranges`
+            if matches!(entry.tag(), gimli::DW_TAG_subprogram)
+                && (entry.attr_value(gimli::DW_AT_low_pc)?.is_some()
+                    || entry.attr_value(gimli::DW_AT_ranges)?.is_some())
+                && let Some(attr) = entry.attr_value(gimli::DW_AT_name)?
+                && dwarf.attr_string(&unit, attr)?.to_string_lossy()? == name
+            {
+                let offset = entry.offset();
+                return Ok(Some((unit, offset)));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+// Finds the DIE of the variable with the given name defined outside of any function,
+// i.e. a global or a static variable of a file
+pub fn find_global_variable<R: gimli::Reader>(
+    dwarf: &Dwarf<R>,
+    name: &str,
+) -> Result<Option<DieRef<R>>> {
+    let mut iter = dwarf.units();
+    while let Some(header) = iter.next()? {
+        let unit = dwarf.unit(header)?;
+        let mut depth = 0;
+        let mut entries = unit.entries();
+        while let Some((delta_depth, entry)) = entries.next_dfs()? {
+            depth += delta_depth;
+            // only the definitions have a location, `extern` declarations don't
+            if depth == 1
+                && entry.tag() == gimli::DW_TAG_variable
+                && entry.attr_value(gimli::DW_AT_location)?.is_some()
+                && get_die_name(dwarf, &unit, entry)?.as_deref() == Some(name)
+            {
+                let offset = entry.offset();
+                return Ok(Some((unit, offset)));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+// Finds the enumerator with the given name, like `RED` of `enum color`.
+// Returns the DIE of its enum along with its value.
+pub fn find_enumerator<R: gimli::Reader>(
+    dwarf: &Dwarf<R>,
+    name: &str,
+) -> Result<Option<(DieRef<R>, i64)>> {
+    let mut iter = dwarf.units();
+    while let Some(header) = iter.next()? {
+        let unit = dwarf.unit(header)?;
+        // the enumerators directly follow the enum they belong to
+        let mut enum_offset = None;
+        let mut entries = unit.entries();
+        while let Some((_, entry)) = entries.next_dfs()? {
+            match entry.tag() {
+                gimli::DW_TAG_enumeration_type => enum_offset = Some(entry.offset()),
+                gimli::DW_TAG_enumerator
+                    if get_die_name(dwarf, &unit, entry)?.as_deref() == Some(name) =>
+                {
+                    let value = match entry.attr_value(gimli::DW_AT_const_value)? {
+                        Some(AttributeValue::Sdata(value)) => value,
+                        Some(attr) => attr.udata_value().unwrap_or(0) as i64,
+                        None => continue,
+                    };
+                    if let Some(offset) = enum_offset {
+                        return Ok(Some(((unit, offset), value)));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+// The end of the prologue is either marked explicitly in the line program,
+// or is taken to be the address of the second row belonging to the function
+fn get_prologue_end<R: gimli::Reader>(unit: &Unit<R>, range: Range<u64>) -> Result<u64> {
+    let Some(program) = unit.line_program.clone() else {
+        return Ok(range.start);
+    };
+
+    let mut second_row = None;
+    let mut rows = program.rows();
+    while let Some((_, row)) = rows.next_row()? {
+        if !range.contains(&row.address()) || row.end_sequence() {
+            continue;
+        }
+        if row.prologue_end() {
+            return Ok(row.address());
+        }
+        if row.address() > range.start && row.is_stmt() {
+            second_row =
+                Some(second_row.map_or(row.address(), |addr: u64| addr.min(row.address())));
+        }
+    }
+
+    Ok(second_row.unwrap_or(range.start))
+}
+
+// Finds the DIE of the type with the given name, like `int`, `color_t` or `struct node`.
+// Complete definitions are preferred over declarations of the same type.
+pub fn find_type_by_name<R: gimli::Reader>(
+    dwarf: &Dwarf<R>,
+    name: &str,
+) -> Result<Option<DieRef<R>>> {
+    let (tags, name) = match name.split_once(' ') {
+        Some(("struct", name)) => (&[gimli::DW_TAG_structure_type][..], name),
+        Some(("union", name)) => (&[gimli::DW_TAG_union_type][..], name),
+        Some(("enum", name)) => (&[gimli::DW_TAG_enumeration_type][..], name),
+        Some(("class", name)) => (&[gimli::DW_TAG_class_type][..], name),
+        // C++ doesn't need the keyword
+        _ => (
+            &[
+                gimli::DW_TAG_base_type,
+                gimli::DW_TAG_typedef,
+                gimli::DW_TAG_structure_type,
+                gimli::DW_TAG_union_type,
+                gimli::DW_TAG_enumeration_type,
+                gimli::DW_TAG_class_type,
+            ][..],
+            name,
+        ),
+    };
+    let name = canonical_type_name(name.trim());
+
+    let mut declaration = None;
+    let mut iter = dwarf.units();
+    while let Some(header) = iter.next()? {
+        let unit = dwarf.unit(header)?;
+        let mut definition = None;
+        let mut declared = None;
+        let mut entries = unit.entries();
+        while let Some((_, entry)) = entries.next_dfs()? {
+            if !tags.contains(&entry.tag())
+                || get_die_name(dwarf, &unit, entry)?.map(|n| canonical_type_name(&n))
+                    != Some(name.clone())
+            {
+                continue;
+            }
+            if entry.attr_value(gimli::DW_AT_declaration)?.is_none() {
+                definition = Some(entry.offset());
+                break;
+            }
+            declared = declared.or(Some(entry.offset()));
+        }
+
+        if let Some(offset) = definition {
+            return Ok(Some((unit, offset)));
+        }
+        if declaration.is_none()
+            && let Some(offset) = declared
+        {
+            declaration = Some((unit, offset));
+        }
+    }
+
+    Ok(declaration)
+}
+
+// Compilers name the base types their own way, e.g. GCC's `long unsigned int` is `unsigned long`.
+// Puts the words in a fixed order and drops the redundant ones.
+pub fn canonical_type_name(name: &str) -> String {
+    let mut words = name.split_whitespace().collect::<Vec<_>>();
+    let is_base = words.iter().all(|word| {
+        matches!(
+            *word,
+            "signed" | "unsigned" | "char" | "short" | "int" | "long" | "float" | "double"
+        )
+    });
+    if !is_base {
+        return words.join(" ");
+    }
+    if words.len() > 1 {
+        words.retain(|word| *word != "int");
+    }
+    if !words.contains(&"char") && words.len() > 1 {
+        words.retain(|word| *word != "signed");
+    }
+    words.sort();
+    words.join(" ")
+}
+
+// Returns the lowest address generated for the given line of the file.
+// If the line has no code associated with it, the next line that does is used instead.
+pub fn get_addr_for_line<R: gimli::Reader>(
+    dwarf: &Dwarf<R>,
+    file: &str,
+    line: usize,
+) -> Result<Option<u64>> {
+    // (line, address) of the best match so far
+    let mut best: Option<(usize, u64)> = None;
+
+    let mut iter = dwarf.units();
+    while let Some(header) = iter.next()? {
+        let unit = dwarf.unit(header)?;
+        let Some(program) = unit.line_program.clone() else {
+            continue;
+        };
+
+        let mut rows = program.rows();
+        while let Some((header, row)) = rows.next_row()? {
+            if !row.is_stmt() || row.end_sequence() {
+                continue;
+            }
+            let Some(row_line) = row.line() else {
+                continue;
+            };
+            let row_line = row_line.get() as usize;
+            if row_line < line || best.is_some_and(|(l, _)| l < row_line) {
+                continue;
+            }
+            if !get_row_path(dwarf, &unit, header, row)?.ends_with(file) {
+                continue;
+            }
+
+            best = match best {
+                Some((l, addr)) if l == row_line => Some((l, addr.min(row.address()))),
+                _ => Some((row_line, row.address())),
+            };
+        }
+    }
+
+    Ok(best.map(|(_, addr)| addr))
+}
+
+fn get_row_path<R: gimli::Reader>(
+    dwarf: &Dwarf<R>,
+    unit: &Unit<R>,
+    header: &gimli::LineProgramHeader<R>,
+    row: &gimli::LineRow,
+) -> Result<PathBuf> {
+    let mut path = path::PathBuf::new();
+    if let Some(file) = row.file(header) {
+        if let Some(ref dir) = unit.comp_dir {
+            path.push(dir.to_string_lossy()?.as_ref());
+        }
+
+        // The directory index 0 is defined to correspond to the compilation unit directory.
+        if file.directory_index() != 0
+            && let Some(dir) = file.directory(header)
+        {
+            path.push(dwarf.attr_string(unit, dir)?.to_string_lossy()?.as_ref());
+        }
+
+        path.push(
+            dwarf
+                .attr_string(unit, file.path_name())?
+                .to_string_lossy()?
+                .as_ref(),
+        );
+    }
+    Ok(path)
+}
+
 pub fn get_compile_unit_for_pc<R: gimli::Reader>(
     dwarf: &Dwarf<R>,
     pc: u64,
@@ -147,8 +490,8 @@ pub fn get_compile_unit_for_pc<R: gimli::Reader>(
         let mut entries = unit.entries();
 
         if let Some((_, cu)) = entries.next_dfs()?
-        && matches!(cu.tag(), gimli::DW_TAG_compile_unit)
-        && get_die_addr_range(cu)?.contains(&pc)
+            && matches!(cu.tag(), gimli::DW_TAG_compile_unit)
+            && die_contains_pc(dwarf, &unit, cu, pc)?
         {
             return Ok(Some(unit));
         }
@@ -156,27 +499,91 @@ pub fn get_compile_unit_for_pc<R: gimli::Reader>(
     Ok(None)
 }
 
-pub fn get_die_addr_range<R: gimli::Reader>(
+// Also handles DIEs with non contiguous ranges (`DW_AT_ranges`), as found in optimised code,
+// and declarations without any addresses
+pub fn die_contains_pc<R: gimli::Reader>(
+    dwarf: &Dwarf<R>,
+    unit: &Unit<R>,
     entry: &DebuggingInformationEntry<R>,
-) -> Result<Range<u64>> {
-    let gimli::AttributeValue::Addr(low_pc)   = entry.attr_value(gimli::DW_AT_low_pc)?.unwrap() else {unreachable!()};
-    let gimli::AttributeValue::Udata(high_pc) = entry.attr_value(gimli::DW_AT_high_pc)?.unwrap() else {unreachable!()};
-    Ok(low_pc..low_pc + high_pc)
+    pc: u64,
+) -> Result<bool> {
+    let mut ranges = dwarf.die_ranges(unit, entry)?;
+    while let Some(range) = ranges.next()? {
+        if (range.begin..range.end).contains(&pc) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// The smallest range covering all the addresses of the DIE, which may not be contiguous
+pub fn get_die_bounds<R: gimli::Reader>(
+    dwarf: &Dwarf<R>,
+    unit: &Unit<R>,
+    entry: &DebuggingInformationEntry<R>,
+) -> Result<Option<Range<u64>>> {
+    let mut bounds: Option<Range<u64>> = None;
+    let mut ranges = dwarf.die_ranges(unit, entry)?;
+    while let Some(range) = ranges.next()? {
+        bounds = Some(match bounds {
+            Some(b) => b.start.min(range.begin)..b.end.max(range.end),
+            None => range.begin..range.end,
+        });
+    }
+    Ok(bounds)
+}
+
+// The range of addresses the code of the DIE starts in.
+// For a function split into a hot and a cold part, as GCC does when optimising,
+// that is the first of its ranges, the one its entry point is in.
+pub fn get_die_entry_range<R: gimli::Reader>(
+    dwarf: &Dwarf<R>,
+    unit: &Unit<R>,
+    entry: &DebuggingInformationEntry<R>,
+) -> Result<Option<Range<u64>>> {
+    let mut ranges = dwarf.die_ranges(unit, entry)?;
+    Ok(ranges.next()?.map(|range| range.begin..range.end))
 }
 
-pub fn get_lines_for_unit<R: gimli::Reader>(
+// Returns the rows of the line table that begin a statement within the addresses of the function,
+// in the order of the table. A line may have several of them, e.g. the condition and the increment of a loop.
+pub fn get_function_lines<R: gimli::Reader>(
+    dwarf: &Dwarf<R>,
     unit: &Unit<R>,
-) -> Result<HashMap<usize, ptrace::AddressType>> {
-    let mut lines = HashMap::new();
+    entry: &DebuggingInformationEntry<R>,
+) -> Result<Vec<LineEntry>> {
+    let mut ranges = Vec::new();
+    let mut die_ranges = dwarf.die_ranges(unit, entry)?;
+    while let Some(range) = die_ranges.next()? {
+        ranges.push(range.begin..range.end);
+    }
+
+    let mut lines = Vec::new();
     let mut rows = unit
         .line_program
         .clone()
         .ok_or(anyhow!("Function lines not found"))?
         .rows();
-    while let Some((_, row)) = rows.next_row()? {
-        let line = row.line().unwrap().get();
-        let addr = row.address();
-        lines.insert(line as usize, addr as ptrace::AddressType);
+    while let Some((header, row)) = rows.next_row()? {
+        if row.end_sequence()
+            || !row.is_stmt()
+            || !ranges.iter().any(|range| range.contains(&row.address()))
+        {
+            continue;
+        }
+        let Some(line) = row.line() else {
+            continue;
+        };
+        let col = match row.column() {
+            gimli::ColumnType::LeftEdge => 0,
+            gimli::ColumnType::Column(column) => column.get(),
+        } as usize;
+        lines.push(LineEntry {
+            addr: row.address(),
+            path: get_row_path(dwarf, unit, header, row)?,
+            line: line.get() as usize,
+            col,
+        });
     }
     Ok(lines)
 }
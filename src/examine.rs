@@ -0,0 +1,207 @@
+use anyhow::{anyhow, Result};
+
+use crate::{disasm, format, tracee::Tracee, unwind::Unwinder};
+
+// Number of bytes shown on each line, next to their ASCII
+const LINE_BYTES: usize = 16;
+
+// Most bytes shown by one command, so that a mistyped count isn't read into memory all at once
+const MAX_BYTES: usize = 64 * 1024;
+
+// What `x/<count><letter><size>` shows: `count` units of `size` bytes in the format named by `letter`,
+// or `count` strings / instructions
+#[derive(Debug, Clone, Copy)]
+pub struct ExamineFormat {
+    pub count: usize,
+    pub letter: char,
+    pub size: usize,
+}
+
+impl Default for ExamineFormat {
+    fn default() -> Self {
+        Self {
+            count: 1,
+            letter: 'x',
+            size: 4,
+        }
+    }
+}
+
+impl ExamineFormat {
+    // Parses what follows the slash, e.g. `4xw`.
+    // As in GDB, the letter and the size default to the last ones used and the count to 1.
+    pub fn parse(spec: &str, last: ExamineFormat) -> Result<Self> {
+        let digits = spec
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(spec.len());
+        let count = if digits == 0 {
+            1
+        } else {
+            spec[..digits].parse()?
+        };
+        if count == 0 {
+            return Err(anyhow!("The count must be at least 1"));
+        }
+
+        let mut letter = None;
+        let mut size = None;
+        for c in spec[digits..].chars() {
+            match c {
+                'b' => size = Some(1),
+                'h' => size = Some(2),
+                'w' => size = Some(4),
+                'g' => size = Some(8),
+                'x' | 'd' | 'u' | 'o' | 't' | 'c' | 'f' | 's' | 'i' => letter = Some(c),
+                _ => return Err(anyhow!("Invalid format letter: {}", c)),
+            }
+        }
+        let letter = letter.unwrap_or(last.letter);
+        let size = match (letter, size) {
+            ('s', Some(size)) if size != 1 => {
+                return Err(anyhow!(
+                    "Only strings of single byte characters can be examined"
+                ))
+            }
+            (_, Some(size)) => size,
+            ('c' | 's', None) => 1,
+            ('f', None) if !matches!(last.size, 4 | 8) => 8,
+            (_, None) => last.size,
+        };
+
+        Ok(Self {
+            count,
+            letter,
+            size,
+        })
+    }
+}
+
+// Prints the memory at `addr` in the format.
+// Returns the address following what was shown, which a plain `x` carries on from.
+pub fn examine(
+    tracee: &Tracee,
+    unwinder: &mut Unwinder,
+    addr: u64,
+    fmt: ExamineFormat,
+) -> Result<u64> {
+    match fmt.letter {
+        's' => examine_strings(tracee, unwinder, addr, fmt.count),
+        'i' => disasm::print_count(tracee, unwinder, addr, fmt.count),
+        _ => examine_units(tracee, unwinder, addr, fmt),
+    }
+}
+
+// Prints a hexdump like table, each line starting with its address and ending with its bytes in ASCII
+fn examine_units(
+    tracee: &Tracee,
+    unwinder: &mut Unwinder,
+    addr: u64,
+    fmt: ExamineFormat,
+) -> Result<u64> {
+    let len = fmt
+        .count
+        .checked_mul(fmt.size)
+        .filter(|&len| len <= MAX_BYTES)
+        .ok_or(anyhow!(
+            "At most {} bytes can be examined at once",
+            MAX_BYTES
+        ))?;
+    let bytes = tracee.read_bytes(addr, len)?;
+    let per_line = (LINE_BYTES / fmt.size).max(1);
+
+    let mut lines = Vec::new();
+    for (i, chunk) in bytes.chunks(per_line * fmt.size).enumerate() {
+        let line_addr = addr + (i * per_line * fmt.size) as u64;
+        let units = chunk
+            .chunks(fmt.size)
+            .map(|unit| format_unit(fmt.letter, unit))
+            .collect::<Vec<_>>();
+        lines.push((label(tracee, unwinder, line_addr)?, units, chunk));
+    }
+
+    // the columns are aligned, including the ASCII of a last line that isn't full
+    let label_width = lines.iter().map(|(l, ..)| l.len()).max().unwrap_or(0);
+    let unit_width = lines
+        .iter()
+        .flat_map(|(_, units, _)| units.iter().map(String::len))
+        .max()
+        .unwrap_or(0);
+    let units_width = lines.first().map_or(0, |(_, units, _)| units.len()) * (unit_width + 2) - 2;
+    for (label, units, chunk) in lines {
+        let units = units
+            .iter()
+            .map(|unit| format!("{:>w$}", unit, w = unit_width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!(
+            "{:<lw$}  {:<uw$}  |{}|",
+            label,
+            units,
+            ascii(chunk),
+            lw = label_width,
+            uw = units_width
+        );
+    }
+
+    Ok(addr + len as u64)
+}
+
+fn examine_strings(
+    tracee: &Tracee,
+    unwinder: &mut Unwinder,
+    mut addr: u64,
+    count: usize,
+) -> Result<u64> {
+    for _ in 0..count {
+        let (s, truncated) = format::read_string(tracee, addr)
+            .ok_or(anyhow!("Cannot access memory at address {:#x}", addr))?;
+        println!(
+            "{}  {}",
+            label(tracee, unwinder, addr)?,
+            format::quote_string(&s, truncated)
+        );
+        // a cut off string goes on where it was cut off
+        addr += s.len() as u64 + if truncated { 0 } else { 1 };
+    }
+    Ok(addr)
+}
+
+fn format_unit(letter: char, bytes: &[u8]) -> String {
+    let raw = format::to_u64(bytes);
+    match letter {
+        'x' => format!("{:#0w$x}", raw, w = 2 + 2 * bytes.len()),
+        'd' => format::to_i64(bytes).to_string(),
+        'u' => raw.to_string(),
+        'o' if raw == 0 => "0".to_owned(),
+        'o' => format!("0{:o}", raw),
+        't' => format!("{:0w$b}", raw, w = 8 * bytes.len()),
+        'c' => format!("{} '{}'", format::to_i64(bytes), format::escape(bytes[0])),
+        // there are no floats of other sizes, those are shown as integers like GDB does
+        'f' if matches!(bytes.len(), 4 | 8) => format::format_base(gimli::DW_ATE_float, bytes),
+        'f' => format::to_i64(bytes).to_string(),
+        _ => unreachable!("format letters are checked when parsed"),
+    }
+}
+
+// The address along with the symbol it belongs to, e.g. `0x401126 <main+4>:`
+fn label(tracee: &Tracee, unwinder: &mut Unwinder, addr: u64) -> Result<String> {
+    let label = match unwinder.symbol_at(tracee.pid, addr)? {
+        Some((name, 0)) => format!("{:#x} <{}>:", addr, name),
+        Some((name, offset)) => format!("{:#x} <{}+{}>:", addr, name, offset),
+        None => format!("{:#x}:", addr),
+    };
+    Ok(label)
+}
+
+fn ascii(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
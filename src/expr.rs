@@ -0,0 +1,1779 @@
+use anyhow::{anyhow, Result};
+use nix::unistd::Pid;
+use std::fmt;
+
+use crate::{
+    dwarf, format, infcall, registers,
+    tracee::Tracee,
+    types::{self, Bitfield, Type, TypeId, TypeTable},
+    unwind::{Frame, Unwinder},
+    variable::{self, VarLocation, Variable},
+};
+
+// The C types usable in casts even if the program never uses them.
+// Literals and the results of arithmetic have these types.
+const BUILTIN_TYPES: [(&str, usize, gimli::DwAte); 14] = [
+    ("char", 1, gimli::DW_ATE_signed_char),
+    ("signed char", 1, gimli::DW_ATE_signed_char),
+    ("unsigned char", 1, gimli::DW_ATE_unsigned_char),
+    ("short", 2, gimli::DW_ATE_signed),
+    ("unsigned short", 2, gimli::DW_ATE_unsigned),
+    ("int", 4, gimli::DW_ATE_signed),
+    ("unsigned int", 4, gimli::DW_ATE_unsigned),
+    ("long", 8, gimli::DW_ATE_signed),
+    ("unsigned long", 8, gimli::DW_ATE_unsigned),
+    ("long long", 8, gimli::DW_ATE_signed),
+    ("unsigned long long", 8, gimli::DW_ATE_unsigned),
+    ("float", 4, gimli::DW_ATE_float),
+    ("double", 8, gimli::DW_ATE_float),
+    ("_Bool", 1, gimli::DW_ATE_boolean),
+];
+
+// The two character operators have to come before their one character prefixes
+const PUNCTUATION: [&str; 30] = [
+    "->", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "<", ">", "&",
+    "|", "^", "!", "~", "(", ")", "[", "]", ".", "{", "}", "=", ",",
+];
+
+// The result of an expression, along with where it lives when it is an object of the tracee
+#[derive(Debug, Clone)]
+pub struct Value {
+    pub ty: TypeId,
+    pub storage: Storage,
+}
+
+#[derive(Debug, Clone)]
+pub enum Storage {
+    // the result of a computation, or a variable of which only the value is known
+    Computed(Vec<u8>),
+    // an object in memory, only read when its value is needed
+    Memory(u64),
+    // a register of the selected frame, like `$rax` or a variable kept in a register, and its value
+    Register(registers::Register, u64),
+    // a bitfield of the struct in memory at the address
+    Bitfield(u64, Bitfield),
+    // why the value is unknown, like the variable being optimized out at the pc
+    Unavailable(String),
+}
+
+// What expressions are evaluated against: the stopped tracee, as seen from the selected frame.
+// Without a tracee, only expressions of constants can be evaluated.
+pub struct Context<'a> {
+    tracee: Option<&'a mut Tracee>,
+    types: &'a mut TypeTable,
+    unwinder: &'a mut Unwinder,
+    // the values recorded so far, `$1` being the first one
+    history: &'a [Value],
+    // level of the selected frame in the backtrace
+    frame_index: usize,
+    // unwound the first time it is needed, as most expressions don't use it
+    frame: Option<Frame>,
+    // the variables visible in the frame, also looked up on first use
+    variables: Option<Vec<Variable>>,
+}
+
+// The tracee, for the expressions that need one
+fn process<'t>(tracee: &'t mut Option<&mut Tracee>) -> Result<&'t mut Tracee> {
+    tracee.as_deref_mut().ok_or(no_process())
+}
+
+fn no_process() -> anyhow::Error {
+    anyhow!("You can't do that without a process to debug.")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Int {
+        value: u64,
+        unsigned: bool,
+        long: bool,
+        // hexadecimal and octal literals may also be unsigned without a suffix
+        decimal: bool,
+    },
+    Float(f64),
+    Char(u8),
+    Ident(String),
+    // `$rax`, without the `$`
+    Register(String),
+    History(HistoryIndex),
+    Punct(&'static str),
+}
+
+// `$3` is the third value of the history, `$` the last one and `$$2` the one two before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HistoryIndex {
+    Absolute(usize),
+    Relative(usize),
+}
+
+#[derive(Debug)]
+enum Expr {
+    Literal(TypeId, Vec<u8>),
+    Register(registers::Register),
+    History(HistoryIndex),
+    Ident(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Cast(TypeId, Box<Expr>),
+    // `{int} 0x601040`, the object of the type at the address
+    At(TypeId, Box<Expr>),
+    SizeofType(TypeId),
+    Sizeof(Box<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    // `p->x` is `(*p).x`
+    Member(Box<Expr>, String),
+    Assign(Box<Expr>, Box<Expr>),
+    // `f(1, x)`, run in the tracee
+    Call(Box<Expr>, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum UnaryOp {
+    Neg,
+    Not,
+    BitNot,
+    Deref,
+    AddrOf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    Or,
+}
+
+// A value reduced to what arithmetic needs to know about it
+#[derive(Debug, Clone, Copy)]
+enum Scalar {
+    // along with its integer type, or the type of the enum
+    Int(i128, TypeId),
+    Float(f64),
+    // along with the pointer type, arrays and functions having decayed to pointers
+    Pointer(u64, TypeId),
+}
+
+impl<'a> Context<'a> {
+    pub fn new(
+        tracee: Option<&'a mut Tracee>,
+        types: &'a mut TypeTable,
+        unwinder: &'a mut Unwinder,
+        history: &'a [Value],
+        frame_index: usize,
+    ) -> Self {
+        Self {
+            tracee,
+            types,
+            unwinder,
+            history,
+            frame_index,
+            frame: None,
+            variables: None,
+        }
+    }
+
+    // Evaluates a C expression like `p->next->value + 1`, `*(int *)($rsp + 8)` or `&main`
+    pub fn evaluate(&mut self, input: &str) -> Result<Value> {
+        let tokens = tokenize(input)?;
+        if tokens.is_empty() {
+            return Err(anyhow!("No expression provided"));
+        }
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            ctx: self,
+        };
+        let expr = parser.assignment()?;
+        if let Some(token) = parser.peek() {
+            return Err(anyhow!("Unexpected {} in expression", token));
+        }
+        self.eval(&expr)
+    }
+
+    // Looks up a type by its C name, e.g. `unsigned long` or `struct node *`
+    pub fn lookup_type(&mut self, name: &str) -> Result<TypeId> {
+        let base = name.trim_end_matches(|c: char| c == '*' || c.is_whitespace());
+        let pointers = name[base.len()..].matches('*').count();
+        // qualifiers don't change how the value is represented
+        let base = base
+            .split_whitespace()
+            .filter(|word| !matches!(*word, "const" | "volatile"))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let mut ty = if base == "void" {
+            types::VOID
+        } else {
+            let found = match self.tracee.as_deref_mut() {
+                Some(tracee) => {
+                    let dwarf = tracee.load_dwarf()?;
+                    match dwarf::find_type_by_name(&dwarf, &base)? {
+                        Some((unit, offset)) => Some(self.types.load(&dwarf, &unit, offset)?),
+                        None => None,
+                    }
+                }
+                // without a program, only the types of C itself are known
+                None => None,
+            };
+            match found {
+                Some(ty) => ty,
+                None => self
+                    .builtin(&base)
+                    .ok_or(anyhow!("No symbol \"{}\" in current context", base))?,
+            }
+        };
+        for _ in 0..pointers {
+            ty = self.types.pointer_to(ty);
+        }
+        Ok(ty)
+    }
+
+    // Renders the value the way `print` shows it
+    pub fn format(&mut self, value: &Value) -> Result<String> {
+        if let Storage::Unavailable(ref reason) = value.storage {
+            return Ok(format!("<{}>", reason));
+        }
+        if let (Type::Function { .. }, Storage::Memory(addr)) =
+            (self.types.get(self.types.strip(value.ty)), &value.storage)
+        {
+            return Ok(format!("{{{}}} {:#x}", self.types.name(value.ty), addr));
+        }
+        let bytes = self.read(value)?;
+        Ok(format::format_value(
+            process(&mut self.tracee)?,
+            self.types,
+            value.ty,
+            &bytes,
+        ))
+    }
+
+    // The bytes of the value, read from the tracee if it lives there
+    pub fn read(&mut self, value: &Value) -> Result<Vec<u8>> {
+        let size = self.types.size(value.ty);
+        match value.storage {
+            Storage::Computed(ref bytes) => Ok(bytes.clone()),
+            Storage::Memory(addr) => process(&mut self.tracee)?.read_bytes(addr, size),
+            Storage::Register(_, value) => Ok(value.to_le_bytes()[..size.min(8)].to_vec()),
+            Storage::Bitfield(addr, bf) => {
+                let bytes = process(&mut self.tracee)?
+                    .read_bytes(addr, (bf.bit_offset + bf.bit_size).div_ceil(8) as usize)?;
+                Ok(format::extract_bitfield(self.types, value.ty, bf, &bytes))
+            }
+            Storage::Unavailable(ref reason) => Err(anyhow!("Value is not available: {}", reason)),
+        }
+    }
+
+    // The value as an integer, e.g. to be used as an address
+    pub fn get_u64(&mut self, value: &Value) -> Result<u64> {
+        match self.get_scalar(value)? {
+            Scalar::Int(value, _) => Ok(value as u64),
+            Scalar::Pointer(addr, _) => Ok(addr),
+            Scalar::Float(_) => Err(anyhow!("Expected an integer or a pointer")),
+        }
+    }
+
+    // Whether the value is non-zero, as a condition in C
+    pub fn is_true(&mut self, value: &Value) -> Result<bool> {
+        let res = match self.get_scalar(value)? {
+            Scalar::Int(value, _) => value != 0,
+            Scalar::Float(value) => value != 0.0,
+            Scalar::Pointer(addr, _) => addr != 0,
+        };
+        Ok(res)
+    }
+
+    pub fn size_of(&self, ty: TypeId) -> usize {
+        self.types.size(ty)
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<Value> {
+        let value = match expr {
+            Expr::Literal(ty, bytes) => Value {
+                ty: *ty,
+                storage: Storage::Computed(bytes.clone()),
+            },
+            Expr::Register(reg) => self.register(*reg)?,
+            Expr::History(index) => self.history_value(*index)?,
+            Expr::Ident(name) => self.identifier(name)?,
+            Expr::Unary(op, operand) => {
+                let operand = self.eval(operand)?;
+                self.unary(*op, operand)?
+            }
+            // the right hand side is only evaluated if it decides the result
+            Expr::Binary(op @ (BinaryOp::And | BinaryOp::Or), lhs, rhs) => {
+                let lhs = self.eval(lhs)?;
+                let mut res = self.is_true(&lhs)?;
+                if res == (*op == BinaryOp::And) {
+                    let rhs = self.eval(rhs)?;
+                    res = self.is_true(&rhs)?;
+                }
+                let int = self.builtin_type("int");
+                self.make_int(res as i128, int)
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.eval(lhs)?;
+                let rhs = self.eval(rhs)?;
+                self.binary(*op, lhs, rhs)?
+            }
+            Expr::Cast(ty, operand) => {
+                // a function without debug info is taken to return what its call is cast to
+                let operand = match &**operand {
+                    Expr::Call(func, args) => self.eval_call(func, args, Some(*ty))?,
+                    operand => self.eval(operand)?,
+                };
+                self.cast(operand, *ty)?
+            }
+            Expr::At(ty, operand) => {
+                let addr = self.eval(operand)?;
+                Value {
+                    ty: *ty,
+                    storage: Storage::Memory(self.get_u64(&addr)?),
+                }
+            }
+            Expr::SizeofType(ty) => self.size_value(*ty),
+            Expr::Sizeof(operand) => {
+                let operand = self.eval(operand)?;
+                self.size_value(operand.ty)
+            }
+            Expr::Index(array, index) => {
+                let array = self.eval(array)?;
+                let index = self.eval(index)?;
+                self.index(array, index)?
+            }
+            Expr::Member(operand, name) => {
+                let operand = self.eval(operand)?;
+                self.member(operand, name)?
+            }
+            Expr::Assign(lhs, rhs) => {
+                let lhs = self.eval(lhs)?;
+                let rhs = self.eval(rhs)?;
+                self.assign(lhs, rhs)?
+            }
+            Expr::Call(func, args) => self.eval_call(func, args, None)?,
+        };
+        Ok(value)
+    }
+
+    fn eval_call(&mut self, func: &Expr, args: &[Expr], cast: Option<TypeId>) -> Result<Value> {
+        let name = match func {
+            Expr::Ident(name) => Some(name.as_str()),
+            _ => None,
+        };
+        let func = self.eval(func)?;
+        let args = args
+            .iter()
+            .map(|arg| self.eval(arg))
+            .collect::<Result<Vec<_>>>()?;
+        self.call(func, name, args, cast)
+    }
+
+    fn unary(&mut self, op: UnaryOp, value: Value) -> Result<Value> {
+        match op {
+            UnaryOp::Deref => self.deref(value),
+            UnaryOp::AddrOf => {
+                let addr = self.address_of(&value)?;
+                let ty = self.types.pointer_to(value.ty);
+                Ok(Value {
+                    ty,
+                    storage: Storage::Computed(addr.to_le_bytes().to_vec()),
+                })
+            }
+            UnaryOp::Not => {
+                let res = !self.is_true(&value)?;
+                let int = self.builtin_type("int");
+                Ok(self.make_int(res as i128, int))
+            }
+            UnaryOp::Neg => match self.get_scalar(&value)? {
+                Scalar::Int(value, ty) => {
+                    let ty = self.arithmetic_type(ty, ty);
+                    Ok(self.make_int(-value, ty))
+                }
+                Scalar::Float(value) => {
+                    let double = self.builtin_type("double");
+                    Ok(self.make_float(-value, double))
+                }
+                Scalar::Pointer(..) => Err(anyhow!(
+                    "Argument to arithmetic operation not a number or boolean."
+                )),
+            },
+            UnaryOp::BitNot => match self.get_scalar(&value)? {
+                Scalar::Int(value, ty) => {
+                    let ty = self.arithmetic_type(ty, ty);
+                    Ok(self.make_int(!value, ty))
+                }
+                _ => Err(anyhow!("Argument to complement operation not an integer.")),
+            },
+        }
+    }
+
+    fn binary(&mut self, op: BinaryOp, lhs: Value, rhs: Value) -> Result<Value> {
+        let lhs = self.get_scalar(&lhs)?;
+        let rhs = self.get_scalar(&rhs)?;
+        match (lhs, rhs, op) {
+            // pointer arithmetic is in elements of the type pointed to
+            (Scalar::Pointer(addr, ty), Scalar::Int(offset, _), BinaryOp::Add | BinaryOp::Sub)
+            | (Scalar::Int(offset, _), Scalar::Pointer(addr, ty), BinaryOp::Add) => {
+                let scale = self.pointee_size(ty) as i128;
+                let offset = if op == BinaryOp::Sub { -offset } else { offset };
+                let addr = (addr as i128 + offset * scale) as u64;
+                return Ok(Value {
+                    ty,
+                    storage: Storage::Computed(addr.to_le_bytes().to_vec()),
+                });
+            }
+            (Scalar::Pointer(lhs, ty), Scalar::Pointer(rhs, _), BinaryOp::Sub) => {
+                let scale = self.pointee_size(ty).max(1) as i128;
+                let long = self.builtin_type("long");
+                return Ok(self.make_int((lhs as i128 - rhs as i128) / scale, long));
+            }
+            _ => {}
+        }
+
+        // anything else done with pointers, like comparing them, treats them as plain addresses
+        let unsigned_long = self.builtin_type("unsigned long");
+        let as_number = |scalar| match scalar {
+            Scalar::Pointer(addr, _) => Scalar::Int(addr as i128, unsigned_long),
+            scalar => scalar,
+        };
+        let int = self.builtin_type("int");
+        match (as_number(lhs), as_number(rhs)) {
+            (Scalar::Int(lhs, lhs_ty), Scalar::Int(rhs, rhs_ty)) => {
+                let ty = self.arithmetic_type(lhs_ty, rhs_ty);
+                let lhs = self.wrap(lhs, ty);
+                let rhs = self.wrap(rhs, ty);
+                let res = match op {
+                    BinaryOp::Mul => lhs.wrapping_mul(rhs),
+                    BinaryOp::Div | BinaryOp::Rem if rhs == 0 => {
+                        return Err(anyhow!("Division by zero"))
+                    }
+                    BinaryOp::Div => lhs / rhs,
+                    BinaryOp::Rem => lhs % rhs,
+                    BinaryOp::Add => lhs + rhs,
+                    BinaryOp::Sub => lhs - rhs,
+                    BinaryOp::Shl => lhs << (rhs & 0x3f),
+                    BinaryOp::Shr => lhs >> (rhs & 0x3f),
+                    BinaryOp::BitAnd => lhs & rhs,
+                    BinaryOp::BitXor => lhs ^ rhs,
+                    BinaryOp::BitOr => lhs | rhs,
+                    op => return Ok(self.make_int(compare(op, lhs, rhs) as i128, int)),
+                };
+                Ok(self.make_int(res, ty))
+            }
+            (lhs, rhs) => {
+                let (lhs, rhs) = (lhs.to_f64(), rhs.to_f64());
+                let res = match op {
+                    BinaryOp::Mul => lhs * rhs,
+                    BinaryOp::Div => lhs / rhs,
+                    BinaryOp::Add => lhs + rhs,
+                    BinaryOp::Sub => lhs - rhs,
+                    BinaryOp::Lt
+                    | BinaryOp::Le
+                    | BinaryOp::Gt
+                    | BinaryOp::Ge
+                    | BinaryOp::Eq
+                    | BinaryOp::Ne => {
+                        return Ok(self.make_int(compare(op, lhs, rhs) as i128, int));
+                    }
+                    _ => return Err(anyhow!("Integer only operation")),
+                };
+                let double = self.builtin_type("double");
+                Ok(self.make_float(res, double))
+            }
+        }
+    }
+
+    // Stores the value in the object, converted to its type as C does.
+    // Exactly the bytes of the object are written, not the whole words they are part of.
+    fn assign(&mut self, lhs: Value, rhs: Value) -> Result<Value> {
+        let rhs = self.cast(rhs, lhs.ty)?;
+        let bytes = self.read(&rhs)?;
+        match lhs.storage {
+            Storage::Memory(addr) => {
+                process(&mut self.tracee)?.write_bytes(addr, &bytes)?;
+                Ok(lhs)
+            }
+            Storage::Bitfield(addr, bf) => {
+                // only the bytes holding the bits are rewritten
+                let start = addr + bf.bit_offset / 8;
+                let shift = bf.bit_offset % 8;
+                let len = (shift + bf.bit_size).div_ceil(8) as usize;
+                let mut old = [0; 16];
+                old[..len].copy_from_slice(&process(&mut self.tracee)?.read_bytes(start, len)?);
+
+                let mask = ((1u128 << bf.bit_size) - 1) << shift;
+                let new = ((format::to_u64(&bytes) as u128) << shift) & mask;
+                let value = (u128::from_le_bytes(old) & !mask) | new;
+                process(&mut self.tracee)?.write_bytes(start, &value.to_le_bytes()[..len])?;
+                Ok(lhs)
+            }
+            Storage::Register(reg, old) => {
+                // the callers' registers are only known as the unwinder recovered them
+                if self.frame_index != 0 {
+                    return Err(anyhow!(
+                        "Registers can only be written in the innermost frame"
+                    ));
+                }
+                let mut value = old.to_le_bytes();
+                let len = bytes.len().min(value.len());
+                value[..len].copy_from_slice(&bytes[..len]);
+                let value = u64::from_le_bytes(value);
+                registers::set_reg_value(self.pid()?, reg, value)?;
+                Ok(Value {
+                    ty: lhs.ty,
+                    storage: Storage::Register(reg, value),
+                })
+            }
+            _ => Err(anyhow!("Left operand of assignment is not an lvalue.")),
+        }
+    }
+
+    // Calls the function, or the function the pointer points to, in the tracee.
+    // The arguments are converted to the types of the parameters,
+    // the ones matching the `...` of a variadic function are promoted like C does.
+    // `cast` is the type the call is cast to, which a function without debug info is taken to return.
+    // `name` is the one the function is called by, if any, for the errors.
+    fn call(
+        &mut self,
+        func: Value,
+        name: Option<&str>,
+        args: Vec<Value>,
+        cast: Option<TypeId>,
+    ) -> Result<Value> {
+        let func = match *self.types.get(self.types.strip(func.ty)) {
+            Type::Pointer { .. } => self.deref(func)?,
+            _ => func,
+        };
+        let Type::Function {
+            ret,
+            ref params,
+            variadic,
+        } = *self.types.get(self.types.strip(func.ty))
+        else {
+            return Err(anyhow!(
+                "Cannot perform a function call on a value that is not a function"
+            ));
+        };
+        let params = params.clone();
+        if args.len() < params.len() {
+            return Err(anyhow!("Too few arguments in function call."));
+        }
+        if args.len() > params.len() && !variadic {
+            return Err(anyhow!("Too many arguments in function call."));
+        }
+        let mut addr = self.address_of(&func)?;
+        let ret = match cast {
+            _ if ret != self.unknown_return_type() => ret,
+            Some(ty) => ty,
+            None => {
+                let name = match (name, self.unwinder.symbol_at(self.pid()?, addr)?) {
+                    (Some(name), _) => name.to_owned(),
+                    (None, Some((symbol, 0))) => symbol,
+                    (None, _) => format!("{:#x}", addr),
+                };
+                return Err(anyhow!(
+                    "'{}' has unknown return type; cast the call to its declared return type",
+                    name
+                ));
+            }
+        };
+        if self.unwinder.is_ifunc(self.pid()?, addr)? {
+            addr = self.resolve_ifunc(addr)?;
+        }
+
+        let mut marshalled = Vec::new();
+        for (i, arg) in args.into_iter().enumerate() {
+            let arg = match params.get(i) {
+                Some(&param) => self.cast(arg, param)?,
+                None => self.promote(arg)?,
+            };
+            marshalled.push((arg.ty, self.read(&arg)?));
+        }
+        let bytes = infcall::call_function(
+            process(&mut self.tracee)?,
+            self.types,
+            addr,
+            ret,
+            &marshalled,
+        )?;
+        Ok(Value {
+            ty: ret,
+            storage: Storage::Computed(bytes),
+        })
+    }
+
+    // The default argument promotions: `float` to `double`, integers smaller than `int` to `int`,
+    // and arrays and functions to pointers
+    fn promote(&mut self, value: Value) -> Result<Value> {
+        match *self.types.get(self.types.strip(value.ty)) {
+            Type::Base {
+                encoding: gimli::DW_ATE_float,
+                size: 4,
+                ..
+            } => {
+                let double = self.builtin_type("double");
+                self.cast(value, double)
+            }
+            Type::Base { size, .. } | Type::Enum { size, .. } if size < 4 => {
+                let int = self.builtin_type("int");
+                self.cast(value, int)
+            }
+            Type::Array { .. } | Type::Function { .. } => {
+                let Scalar::Pointer(addr, ty) = self.get_scalar(&value)? else {
+                    unreachable!("arrays and functions decay to pointers");
+                };
+                Ok(Value {
+                    ty,
+                    storage: Storage::Computed(addr.to_le_bytes().to_vec()),
+                })
+            }
+            _ => Ok(value),
+        }
+    }
+
+    // Whether the type is `void`, like the one of a call to a function returning nothing
+    pub fn is_void(&self, ty: TypeId) -> bool {
+        self.types.strip(ty) == types::VOID
+    }
+
+    // Converts the value to the type, like a C cast does
+    pub fn cast(&mut self, value: Value, ty: TypeId) -> Result<Value> {
+        let target = self.types.strip(ty);
+        // the same type under another name, e.g. through a typedef, is left as it is,
+        // including the ones arithmetic doesn't handle like `_Complex double`
+        if self.types.strip(value.ty) == target && target != types::VOID {
+            return Ok(Value {
+                ty,
+                storage: value.storage,
+            });
+        }
+        match *self.types.get(target) {
+            Type::Void => Ok(Value {
+                ty,
+                storage: Storage::Computed(Vec::new()),
+            }),
+            Type::Struct { .. } | Type::Array { .. } | Type::Function { .. } => {
+                Err(anyhow!("Invalid cast."))
+            }
+            Type::Base {
+                encoding: gimli::DW_ATE_float,
+                ..
+            } => {
+                let value = self.get_scalar(&value)?.to_f64();
+                Ok(self.make_float(value, ty))
+            }
+            Type::Base {
+                encoding: gimli::DW_ATE_boolean,
+                ..
+            } => {
+                let value = self.is_true(&value)?;
+                Ok(self.make_int(value as i128, ty))
+            }
+            _ => {
+                let value = match self.get_scalar(&value)? {
+                    Scalar::Int(value, _) => value,
+                    Scalar::Float(value) => value as i128,
+                    Scalar::Pointer(addr, _) => addr as i128,
+                };
+                Ok(self.make_int(value, ty))
+            }
+        }
+    }
+
+    fn deref(&mut self, value: Value) -> Result<Value> {
+        let stripped = self.types.strip(value.ty);
+        match *self.types.get(stripped) {
+            Type::Pointer { target, .. } => {
+                if let Type::Void = self.types.get(self.types.strip(target)) {
+                    return Err(anyhow!("Attempt to take contents of a non-pointer value."));
+                }
+                let addr = self.get_u64(&value)?;
+                Ok(Value {
+                    ty: target,
+                    storage: Storage::Memory(addr),
+                })
+            }
+            // the first element
+            Type::Array { elem, .. } => Ok(self.sub_value(&value, elem, 0)),
+            Type::Function { .. } => Ok(value),
+            _ => match self.get_scalar(&value)? {
+                // `*0x601040`, a whole word of memory
+                Scalar::Int(addr, _) => Ok(Value {
+                    ty: self.builtin_type("long"),
+                    storage: Storage::Memory(addr as u64),
+                }),
+                _ => Err(anyhow!("Attempt to take contents of a non-pointer value.")),
+            },
+        }
+    }
+
+    fn index(&mut self, array: Value, index: Value) -> Result<Value> {
+        let Scalar::Int(i, _) = self.get_scalar(&index)? else {
+            return Err(anyhow!("Array index is not an integer"));
+        };
+        let stripped = self.types.strip(array.ty);
+        match *self.types.get(stripped) {
+            // arrays not in memory, like the ones held in registers, have no address to index from
+            Type::Array { elem, .. } if !matches!(array.storage, Storage::Memory(_)) => {
+                let elem_size = self.types.size(elem);
+                let offset = usize::try_from(i)
+                    .ok()
+                    .map(|i| i * elem_size)
+                    .filter(|offset| offset + elem_size <= self.types.size(array.ty))
+                    .ok_or(anyhow!("No such vector element"))?;
+                Ok(self.sub_value(&array, elem, offset))
+            }
+            Type::Array { .. } | Type::Pointer { .. } => {
+                let ptr = self.binary(BinaryOp::Add, array, index)?;
+                self.deref(ptr)
+            }
+            _ => Err(anyhow!(
+                "Cannot subscript something of type `{}'",
+                self.types.name(array.ty)
+            )),
+        }
+    }
+
+    fn member(&mut self, value: Value, name: &str) -> Result<Value> {
+        let Type::Struct { members, .. } = self.types.get(self.types.strip(value.ty)) else {
+            return Err(anyhow!(
+                "Attempt to extract a component of a value that is not a structure."
+            ));
+        };
+        for member in members.clone() {
+            match member.name {
+                Some(ref member_name) if member_name == name => {
+                    let Some(bf) = member.bitfield else {
+                        return Ok(self.sub_value(&value, member.ty, member.offset));
+                    };
+                    if let Storage::Memory(addr) = value.storage {
+                        return Ok(Value {
+                            ty: member.ty,
+                            storage: Storage::Bitfield(addr, bf),
+                        });
+                    }
+                    let bytes = self.read(&value)?;
+                    return Ok(Value {
+                        ty: member.ty,
+                        storage: Storage::Computed(format::extract_bitfield(
+                            self.types, member.ty, bf, &bytes,
+                        )),
+                    });
+                }
+                // the members of anonymous structs and unions are accessed as if they were its own
+                None if self.has_member(member.ty, name) => {
+                    let nested = self.sub_value(&value, member.ty, member.offset);
+                    return self.member(nested, name);
+                }
+                _ => {}
+            }
+        }
+        Err(anyhow!("There is no member named {}.", name))
+    }
+
+    fn has_member(&self, ty: TypeId, name: &str) -> bool {
+        match self.types.get(self.types.strip(ty)) {
+            Type::Struct { members, .. } => members.iter().any(|member| match member.name {
+                Some(ref member_name) => member_name == name,
+                None => self.has_member(member.ty, name),
+            }),
+            _ => false,
+        }
+    }
+
+    // The part of the value at `offset` bytes from its start, like a member of a struct
+    fn sub_value(&self, value: &Value, ty: TypeId, offset: usize) -> Value {
+        let size = self.types.size(ty);
+        let slice = |bytes: &[u8]| {
+            bytes
+                .get(offset..offset + size)
+                .map(<[u8]>::to_vec)
+                .unwrap_or_default()
+        };
+        let storage = match value.storage {
+            Storage::Memory(addr) => Storage::Memory(addr + offset as u64),
+            Storage::Computed(ref bytes) => Storage::Computed(slice(bytes)),
+            Storage::Register(_, value) => Storage::Computed(slice(&value.to_le_bytes())),
+            Storage::Bitfield(..) => unreachable!("bitfields are never structs or arrays"),
+            Storage::Unavailable(ref reason) => Storage::Unavailable(reason.clone()),
+        };
+        Value { ty, storage }
+    }
+
+    fn address_of(&self, value: &Value) -> Result<u64> {
+        match value.storage {
+            Storage::Memory(addr) => Ok(addr),
+            _ => Err(anyhow!(
+                "Attempt to take address of value not located in memory."
+            )),
+        }
+    }
+
+    fn register(&mut self, reg: registers::Register) -> Result<Value> {
+        let value = if self.frame_index == 0 {
+            Some(registers::get_reg_value(self.pid()?, reg)?)
+        } else {
+            self.frame()?.get_reg(reg)
+        };
+        // as in GDB, the registers holding code and stack addresses are pointers
+        let ty = match reg {
+            registers::Register::Rip => {
+                let func = self.types.intern(Type::Function {
+                    ret: types::VOID,
+                    params: Vec::new(),
+                    variadic: false,
+                });
+                self.types.pointer_to(func)
+            }
+            registers::Register::Rsp | registers::Register::Rbp => {
+                self.types.pointer_to(types::VOID)
+            }
+            _ => self.builtin_type("long"),
+        };
+        let storage = match value {
+            Some(value) => Storage::Register(reg, value),
+            // the caller saved registers are lost in the frames further out
+            None => Storage::Unavailable("not saved".to_owned()),
+        };
+        Ok(Value { ty, storage })
+    }
+
+    fn history_value(&self, index: HistoryIndex) -> Result<Value> {
+        if self.history.is_empty() {
+            return Err(anyhow!("History is empty."));
+        }
+        let value = match index {
+            HistoryIndex::Absolute(n) => n
+                .checked_sub(1)
+                .and_then(|i| self.history.get(i))
+                .ok_or(anyhow!("History has not yet reached ${}.", n))?,
+            HistoryIndex::Relative(back) => self
+                .history
+                .len()
+                .checked_sub(back + 1)
+                .map(|i| &self.history[i])
+                .ok_or(anyhow!("History has not yet reached $${}.", back))?,
+        };
+        Ok(value.clone())
+    }
+
+    // Looks up the name as a variable, a function or an enumerator, in that order
+    fn identifier(&mut self, name: &str) -> Result<Value> {
+        if let Some(value) = self.find_variable(name)? {
+            return Ok(value);
+        }
+
+        let dwarf = process(&mut self.tracee)?.load_dwarf()?;
+        if let Some((unit, offset)) = dwarf::find_function_by_name(&dwarf, name)? {
+            let range = dwarf::get_die_entry_range(&dwarf, &unit, &unit.entry(offset)?)?
+                .ok_or(anyhow!("Function {} has no addresses", name))?;
+            return Ok(Value {
+                ty: self.types.load(&dwarf, &unit, offset)?,
+                storage: Storage::Memory(process(&mut self.tracee)?.add_load_addr(range.start)),
+            });
+        }
+        if let Some(((unit, offset), value)) = dwarf::find_enumerator(&dwarf, name)? {
+            let ty = self.types.load(&dwarf, &unit, offset)?;
+            return Ok(self.make_int(value as i128, ty));
+        }
+        // functions without debug info are only known by their address,
+        // the ones of the shared libraries included
+        if let Some(addr) = self.unwinder.find_function(self.pid()?, name)? {
+            let ret = self.unknown_return_type();
+            let ty = self.types.intern(Type::Function {
+                ret,
+                params: Vec::new(),
+                variadic: true,
+            });
+            return Ok(Value {
+                ty,
+                storage: Storage::Memory(addr),
+            });
+        }
+
+        Err(anyhow!("No symbol \"{}\" in current context", name))
+    }
+
+    // Calls the resolver of the indirect function for the implementation it picks,
+    // like the dynamic linker does, e.g. the variant of `strlen` for the CPU
+    fn resolve_ifunc(&mut self, resolver: u64) -> Result<u64> {
+        let ulong = self.builtin_type("unsigned long");
+        let bytes =
+            infcall::call_function(process(&mut self.tracee)?, self.types, resolver, ulong, &[])?;
+        Ok(format::to_u64(&bytes))
+    }
+
+    // The variable of that name visible in the selected frame, or else the global one
+    fn find_variable(&mut self, name: &str) -> Result<Option<Value>> {
+        if self.variables.is_none() {
+            // frames without debug info only see the globals
+            let vars = match self.frame() {
+                Ok(_) => variable::get_frame_variables(
+                    process(&mut self.tracee)?,
+                    self.types,
+                    self.frame.as_ref().expect("unwound above"),
+                    self.frame_index,
+                )
+                .unwrap_or_default(),
+                Err(_) => Vec::new(),
+            };
+            self.variables = Some(vars);
+        }
+
+        let vars = self.variables.as_ref().expect("looked up above");
+        // the innermost variable of the name shadows the others
+        if let Some(var) = vars.iter().find(|var| var.name == name) {
+            return Ok(Some(variable_value(var, self.types)));
+        }
+        let var = variable::find_global_variable(process(&mut self.tracee)?, self.types, name)?;
+        Ok(var.map(|var| variable_value(&var, self.types)))
+    }
+
+    fn pid(&self) -> Result<Pid> {
+        self.tracee
+            .as_ref()
+            .map(|tracee| tracee.pid)
+            .ok_or(no_process())
+    }
+
+    // The selected frame, unwound the first time it is needed
+    fn frame(&mut self) -> Result<&Frame> {
+        if self.frame.is_none() {
+            let frames = self.unwinder.backtrace(self.pid()?)?;
+            let frame = frames
+                .into_iter()
+                .nth(self.frame_index)
+                .ok_or(anyhow!("No frame at level {}", self.frame_index))?;
+            self.frame = Some(frame);
+        }
+        Ok(self.frame.as_ref().expect("unwound above"))
+    }
+
+    // The name is a type if it isn't also a variable, e.g. `(x)` is a parenthesized expression
+    fn resolve_type_name(&mut self, words: &[String], pointers: usize) -> Option<TypeId> {
+        if let [word] = words
+            && self.find_variable(word).is_ok_and(|var| var.is_some())
+        {
+            return None;
+        }
+        let name = format!("{} {}", words.join(" "), "*".repeat(pointers));
+        self.lookup_type(&name).ok()
+    }
+
+    fn get_scalar(&mut self, value: &Value) -> Result<Scalar> {
+        let stripped = self.types.strip(value.ty);
+        let scalar = match *self.types.get(stripped) {
+            Type::Base {
+                encoding: gimli::DW_ATE_float,
+                size,
+                ..
+            } => {
+                let bytes = self.read(value)?;
+                match size {
+                    4 => Scalar::Float(f32::from_bits(format::to_u64(&bytes) as u32) as f64),
+                    8 => Scalar::Float(f64::from_bits(format::to_u64(&bytes))),
+                    _ => return Err(anyhow!("Unsupported floating point size: {}", size)),
+                }
+            }
+            Type::Base { .. } | Type::Enum { .. } => {
+                let bytes = self.read(value)?;
+                let value_int = if self.is_unsigned(value.ty) {
+                    format::to_u64(&bytes) as i128
+                } else {
+                    format::to_i64(&bytes) as i128
+                };
+                Scalar::Int(value_int, value.ty)
+            }
+            Type::Pointer { .. } => Scalar::Pointer(format::to_u64(&self.read(value)?), value.ty),
+            // arrays and functions decay to pointers to their first element and to themselves
+            Type::Array { elem, .. } => {
+                let addr = self.address_of(value)?;
+                Scalar::Pointer(addr, self.types.pointer_to(elem))
+            }
+            Type::Function { .. } => {
+                let addr = self.address_of(value)?;
+                Scalar::Pointer(addr, self.types.pointer_to(value.ty))
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Argument to arithmetic operation not a number or boolean."
+                ))
+            }
+        };
+        Ok(scalar)
+    }
+
+    fn is_unsigned(&self, ty: TypeId) -> bool {
+        match *self.types.get(self.types.strip(ty)) {
+            Type::Base { encoding, .. } => matches!(
+                encoding,
+                gimli::DW_ATE_unsigned | gimli::DW_ATE_unsigned_char | gimli::DW_ATE_boolean
+            ),
+            Type::Enum { underlying, .. } => underlying.is_some_and(|ty| self.is_unsigned(ty)),
+            Type::Pointer { .. } => true,
+            _ => false,
+        }
+    }
+
+    // The type that binary operations on integers of the two types are done in.
+    // As in C, both are promoted to at least `int`, then to the larger of the two.
+    fn arithmetic_type(&mut self, lhs: TypeId, rhs: TypeId) -> TypeId {
+        let lhs_size = self.types.size(lhs).min(8);
+        let rhs_size = self.types.size(rhs).min(8);
+        let size = lhs_size.max(rhs_size).max(4);
+        let unsigned = (self.is_unsigned(lhs) && lhs_size == size)
+            || (self.is_unsigned(rhs) && rhs_size == size);
+        self.builtin_type(match (size, unsigned) {
+            (8, false) => "long",
+            (8, true) => "unsigned long",
+            (_, false) => "int",
+            (_, true) => "unsigned int",
+        })
+    }
+
+    // Truncates the integer to the size of the type, as storing it in a value of the type would
+    fn wrap(&self, value: i128, ty: TypeId) -> i128 {
+        let shift = 128 - 8 * self.types.size(ty).clamp(1, 8) as u32;
+        if self.is_unsigned(ty) {
+            ((value << shift) as u128 >> shift) as i128
+        } else {
+            (value << shift) >> shift
+        }
+    }
+
+    fn pointee_size(&self, ptr: TypeId) -> usize {
+        match *self.types.get(self.types.strip(ptr)) {
+            Type::Pointer { target, .. } => self.types.size(target),
+            _ => 1,
+        }
+    }
+
+    fn make_int(&self, value: i128, ty: TypeId) -> Value {
+        let size = self.types.size(ty).min(16);
+        let bytes = self.wrap(value, ty).to_le_bytes()[..size].to_vec();
+        Value {
+            ty,
+            storage: Storage::Computed(bytes),
+        }
+    }
+
+    fn make_float(&self, value: f64, ty: TypeId) -> Value {
+        let bytes = match self.types.size(ty) {
+            4 => (value as f32).to_le_bytes().to_vec(),
+            16 => to_x87(value).to_vec(),
+            _ => value.to_le_bytes().to_vec(),
+        };
+        Value {
+            ty,
+            storage: Storage::Computed(bytes),
+        }
+    }
+
+    fn size_value(&mut self, ty: TypeId) -> Value {
+        let size = self.types.size(ty);
+        let unsigned_long = self.builtin_type("unsigned long");
+        self.make_int(size as i128, unsigned_long)
+    }
+
+    // The type of a literal, `int` unless the value or a suffix asks for more.
+    // As in C, a hexadecimal or octal value too big for `int` is an `unsigned int` if it fits,
+    // where a decimal one becomes a `long`.
+    fn literal_type(&mut self, value: u64, unsigned: bool, long: bool, decimal: bool) -> TypeId {
+        let name = if !long && !unsigned && value <= i32::MAX as u64 {
+            "int"
+        } else if !long && (unsigned || !decimal) && value <= u32::MAX as u64 {
+            "unsigned int"
+        } else if !unsigned && value <= i64::MAX as u64 {
+            "long"
+        } else {
+            "unsigned long"
+        };
+        self.builtin_type(name)
+    }
+
+    fn builtin(&mut self, name: &str) -> Option<TypeId> {
+        let name = dwarf::canonical_type_name(name);
+        let &(builtin, size, encoding) = BUILTIN_TYPES
+            .iter()
+            .find(|(builtin, ..)| dwarf::canonical_type_name(builtin) == name)?;
+        Some(self.types.intern(Type::Base {
+            name: builtin.to_owned(),
+            size,
+            encoding,
+        }))
+    }
+
+    fn builtin_type(&mut self, name: &str) -> TypeId {
+        self.builtin(name)
+            .expect("the name is one of the builtin types")
+    }
+
+    // Stands for what the functions without debug info return,
+    // their calls have to be cast to the type they actually return
+    fn unknown_return_type(&mut self) -> TypeId {
+        self.types.intern(Type::Base {
+            name: "<unknown return type>".to_owned(),
+            size: 0,
+            encoding: gimli::DW_ATE_signed,
+        })
+    }
+}
+
+impl Scalar {
+    fn to_f64(self) -> f64 {
+        match self {
+            Scalar::Int(value, _) => value as f64,
+            Scalar::Float(value) => value,
+            Scalar::Pointer(addr, _) => addr as f64,
+        }
+    }
+}
+
+// The 80 bit extended precision format of `long double`, padded to its 16 bytes
+fn to_x87(value: f64) -> [u8; 16] {
+    let bits = value.to_bits();
+    let sign = (bits >> 63) as u16;
+    let exp = ((bits >> 52) & 0x7ff) as u16;
+    let fraction = bits & ((1 << 52) - 1);
+    // unlike a `double`, the integer bit is explicit
+    let (exp, mantissa) = match exp {
+        0 if fraction == 0 => (0, 0),
+        // subnormal doubles are normal numbers with the wider exponent
+        0 => {
+            let shift = fraction.leading_zeros();
+            (16383 - 1023 + 12 - shift as u16, fraction << shift)
+        }
+        0x7ff => (0x7fff, 1 << 63 | fraction << 11),
+        exp => (exp - 1023 + 16383, 1 << 63 | fraction << 11),
+    };
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&mantissa.to_le_bytes());
+    bytes[8..10].copy_from_slice(&(sign << 15 | exp).to_le_bytes());
+    bytes
+}
+
+fn compare<T: PartialOrd>(op: BinaryOp, lhs: T, rhs: T) -> bool {
+    match op {
+        BinaryOp::Lt => lhs < rhs,
+        BinaryOp::Le => lhs <= rhs,
+        BinaryOp::Gt => lhs > rhs,
+        BinaryOp::Ge => lhs >= rhs,
+        BinaryOp::Eq => lhs == rhs,
+        BinaryOp::Ne => lhs != rhs,
+        _ => unreachable!("not a comparison: {:?}", op),
+    }
+}
+
+fn variable_value(var: &Variable, types: &TypeTable) -> Value {
+    let storage = match var.location {
+        VarLocation::Address(addr) => Storage::Memory(addr),
+        VarLocation::Register(register, value) => {
+            match registers::get_reg_from_dwarf_number(register as u8) {
+                Ok(reg) => Storage::Register(reg, value),
+                Err(_) => {
+                    Storage::Computed(value.to_le_bytes()[..types.size(var.ty).min(8)].to_vec())
+                }
+            }
+        }
+        VarLocation::Value(ref bytes) => {
+            let mut bytes = bytes.clone();
+            bytes.resize(types.size(var.ty), 0);
+            Storage::Computed(bytes)
+        }
+        VarLocation::Unavailable(ref reason) => Storage::Unavailable(reason.clone()),
+    };
+    Value {
+        ty: var.ty,
+        storage,
+    }
+}
+
+struct Parser<'a, 'b> {
+    tokens: Vec<Token>,
+    pos: usize,
+    // whether `(x)` starts a cast depends on `x` being the name of a type
+    ctx: &'a mut Context<'b>,
+}
+
+impl Parser<'_, '_> {
+    // `lhs = rhs`, binding the loosest and grouping to the right
+    fn assignment(&mut self) -> Result<Expr> {
+        let lhs = self.expression(0)?;
+        if self.eat("=") {
+            let rhs = self.assignment()?;
+            return Ok(Expr::Assign(Box::new(lhs), Box::new(rhs)));
+        }
+        Ok(lhs)
+    }
+
+    // Parses the operators binding at least as tightly as `min_prec`, by precedence climbing
+    fn expression(&mut self, min_prec: u8) -> Result<Expr> {
+        let mut lhs = self.unary()?;
+        while let Some(Token::Punct(punct)) = self.peek()
+            && let Some((op, prec)) = binary_op(punct)
+            && prec >= min_prec
+        {
+            self.pos += 1;
+            let rhs = self.expression(prec + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        let op = match self.peek() {
+            Some(Token::Punct("-")) => UnaryOp::Neg,
+            Some(Token::Punct("!")) => UnaryOp::Not,
+            Some(Token::Punct("~")) => UnaryOp::BitNot,
+            Some(Token::Punct("*")) => UnaryOp::Deref,
+            Some(Token::Punct("&")) => UnaryOp::AddrOf,
+            Some(Token::Punct("+")) => {
+                self.pos += 1;
+                return self.unary();
+            }
+            Some(Token::Ident(word)) if word == "sizeof" => {
+                self.pos += 1;
+                let start = self.pos;
+                if self.eat("(") {
+                    if let Some(ty) = self.type_name(")") {
+                        return Ok(Expr::SizeofType(ty));
+                    }
+                    self.pos = start;
+                }
+                return Ok(Expr::Sizeof(Box::new(self.unary()?)));
+            }
+            Some(Token::Punct("(")) => {
+                let start = self.pos;
+                self.pos += 1;
+                if let Some(ty) = self.type_name(")") {
+                    return Ok(Expr::Cast(ty, Box::new(self.unary()?)));
+                }
+                self.pos = start;
+                return self.postfix();
+            }
+            Some(Token::Punct("{")) => {
+                self.pos += 1;
+                let ty = self
+                    .type_name("}")
+                    .ok_or(anyhow!("Expected a type name in {{...}}"))?;
+                return Ok(Expr::At(ty, Box::new(self.unary()?)));
+            }
+            _ => return self.postfix(),
+        };
+        self.pos += 1;
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn postfix(&mut self) -> Result<Expr> {
+        let mut expr = self.primary()?;
+        loop {
+            if self.eat("[") {
+                let index = self.expression(0)?;
+                self.expect("]")?;
+                expr = Expr::Index(Box::new(expr), Box::new(index));
+            } else if self.eat(".") {
+                expr = Expr::Member(Box::new(expr), self.ident()?);
+            } else if self.eat("->") {
+                let deref = Expr::Unary(UnaryOp::Deref, Box::new(expr));
+                expr = Expr::Member(Box::new(deref), self.ident()?);
+            } else if self.eat("(") {
+                let mut args = Vec::new();
+                if !self.eat(")") {
+                    loop {
+                        args.push(self.assignment()?);
+                        if self.eat(")") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                expr = Expr::Call(Box::new(expr), args);
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or(anyhow!("Unexpected end of expression"))?;
+        self.pos += 1;
+        let expr = match token {
+            Token::Int {
+                value,
+                unsigned,
+                long,
+                decimal,
+            } => {
+                let ty = self.ctx.literal_type(value, unsigned, long, decimal);
+                let size = self.ctx.size_of(ty);
+                Expr::Literal(ty, value.to_le_bytes()[..size].to_vec())
+            }
+            Token::Float(value) => {
+                let ty = self.ctx.builtin_type("double");
+                Expr::Literal(ty, value.to_le_bytes().to_vec())
+            }
+            Token::Char(value) => {
+                let ty = self.ctx.builtin_type("char");
+                Expr::Literal(ty, vec![value])
+            }
+            Token::Ident(name) => Expr::Ident(name),
+            Token::Register(name) => Expr::Register(registers::get_reg_from_string(&name)?),
+            Token::History(index) => Expr::History(index),
+            Token::Punct("(") => {
+                let expr = self.assignment()?;
+                self.expect(")")?;
+                expr
+            }
+            token => return Err(anyhow!("Unexpected {} in expression", token)),
+        };
+        Ok(expr)
+    }
+
+    // Reads a type name like `unsigned long` or `struct node *` up to the closing token.
+    // Nothing is consumed if the tokens don't name a type.
+    fn type_name(&mut self, close: &str) -> Option<TypeId> {
+        let start = self.pos;
+        let mut words = Vec::new();
+        while let Some(Token::Ident(word)) = self.peek() {
+            words.push(word.clone());
+            self.pos += 1;
+        }
+        let mut pointers = 0;
+        while self.eat("*") {
+            pointers += 1;
+        }
+        let ty = if !words.is_empty() && self.eat(close) {
+            self.ctx.resolve_type_name(&words, pointers)
+        } else {
+            None
+        };
+        if ty.is_none() {
+            self.pos = start;
+        }
+        ty
+    }
+
+    fn ident(&mut self) -> Result<String> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Ident(name)) => {
+                self.pos += 1;
+                Ok(name.clone())
+            }
+            Some(token) => Err(anyhow!("Expected a member name, found {}", token)),
+            None => Err(anyhow!("Expected a member name")),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Punct(p)) if *p == punct);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<()> {
+        if self.eat(punct) {
+            return Ok(());
+        }
+        match self.peek() {
+            Some(token) => Err(anyhow!("Expected `{}`, found {}", punct, token)),
+            None => Err(anyhow!("Expected `{}` at the end of the expression", punct)),
+        }
+    }
+}
+
+// The binary operator and its precedence, higher binding tighter
+fn binary_op(punct: &str) -> Option<(BinaryOp, u8)> {
+    let op = match punct {
+        "||" => (BinaryOp::Or, 1),
+        "&&" => (BinaryOp::And, 2),
+        "|" => (BinaryOp::BitOr, 3),
+        "^" => (BinaryOp::BitXor, 4),
+        "&" => (BinaryOp::BitAnd, 5),
+        "==" => (BinaryOp::Eq, 6),
+        "!=" => (BinaryOp::Ne, 6),
+        "<" => (BinaryOp::Lt, 7),
+        "<=" => (BinaryOp::Le, 7),
+        ">" => (BinaryOp::Gt, 7),
+        ">=" => (BinaryOp::Ge, 7),
+        "<<" => (BinaryOp::Shl, 8),
+        ">>" => (BinaryOp::Shr, 8),
+        "+" => (BinaryOp::Add, 9),
+        "-" => (BinaryOp::Sub, 9),
+        "*" => (BinaryOp::Mul, 10),
+        "/" => (BinaryOp::Div, 10),
+        "%" => (BinaryOp::Rem, 10),
+        _ => return None,
+    };
+    Some(op)
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let bytes = input.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        let start = i;
+        if c.is_ascii_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit()
+            || (c == b'.' && bytes.get(i + 1).is_some_and(u8::is_ascii_digit))
+        {
+            let is_hex = input[i..].starts_with("0x") || input[i..].starts_with("0X");
+            i += 1;
+            // the sign of an exponent, as in `1e-5`, is part of the number
+            while i < bytes.len()
+                && (bytes[i].is_ascii_alphanumeric()
+                    || bytes[i] == b'.'
+                    || (matches!(bytes[i], b'+' | b'-')
+                        && matches!(bytes[i - 1], b'e' | b'E')
+                        && !is_hex))
+            {
+                i += 1;
+            }
+            tokens.push(parse_number(&input[start..i])?);
+        } else if c == b'$'
+            && !bytes
+                .get(i + 1)
+                .is_some_and(|b| b.is_ascii_alphabetic() || *b == b'_')
+        {
+            // the value history: `$`, `$3`, `$$` or `$$2`
+            i += 1;
+            let relative = bytes.get(i) == Some(&b'$');
+            if relative {
+                i += 1;
+            }
+            let digits = i;
+            while i < bytes.len() && bytes[i].is_ascii_digit() {
+                i += 1;
+            }
+            let n = input[digits..i].parse().ok();
+            tokens.push(Token::History(match (relative, n) {
+                (false, Some(n)) => HistoryIndex::Absolute(n),
+                (false, None) => HistoryIndex::Relative(0),
+                (true, n) => HistoryIndex::Relative(n.unwrap_or(1)),
+            }));
+        } else if c.is_ascii_alphabetic() || c == b'_' || c == b'$' {
+            i += 1;
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            let word = &input[start..i];
+            tokens.push(match word.strip_prefix('$') {
+                Some(reg) => Token::Register(reg.to_owned()),
+                None => Token::Ident(word.to_owned()),
+            });
+        } else if c == b'\'' {
+            let (value, len) = parse_char(&bytes[i + 1..])?;
+            tokens.push(Token::Char(value));
+            i += 1 + len;
+        } else if let Some(punct) = PUNCTUATION
+            .iter()
+            .find(|punct| input[i..].starts_with(**punct))
+        {
+            tokens.push(Token::Punct(punct));
+            i += punct.len();
+        } else {
+            let c = input[i..].chars().next().expect("not at the end");
+            return Err(anyhow!("Invalid character '{}' in expression", c));
+        }
+    }
+    Ok(tokens)
+}
+
+// Parses a decimal, hexadecimal (`0x`) or octal (`0`) integer, with an optional `u` / `l` suffix,
+// or a floating point number
+fn parse_number(s: &str) -> Result<Token> {
+    let invalid = || anyhow!("Invalid number: {}", s);
+    let is_hex = s.starts_with("0x") || s.starts_with("0X");
+    if !is_hex && (s.contains('.') || s.contains(['e', 'E'])) {
+        return s.parse().map(Token::Float).map_err(|_| invalid());
+    }
+
+    let digits = s.trim_end_matches(['u', 'U', 'l', 'L']);
+    let suffix = s[digits.len()..].to_ascii_lowercase();
+    let is_octal = !is_hex && digits.len() > 1 && digits.starts_with('0');
+    let value = if is_hex {
+        u64::from_str_radix(&digits[2..], 16)
+    } else if is_octal {
+        u64::from_str_radix(&digits[1..], 8)
+    } else {
+        digits.parse()
+    }
+    .map_err(|_| invalid())?;
+    Ok(Token::Int {
+        value,
+        unsigned: suffix.contains('u'),
+        long: suffix.contains('l'),
+        decimal: !is_hex && !is_octal,
+    })
+}
+
+// Parses the character literal following a `'`, returning its value and the length of the rest of it
+fn parse_char(bytes: &[u8]) -> Result<(u8, usize)> {
+    let (value, len) = match bytes {
+        [b'\\', b'x', rest @ ..] => {
+            let digits = rest.iter().take_while(|c| c.is_ascii_hexdigit()).count();
+            let hex = std::str::from_utf8(&rest[..digits]).expect("hex digits are ASCII");
+            let value =
+                u8::from_str_radix(hex, 16).map_err(|_| anyhow!("Invalid character escape"))?;
+            (value, 2 + digits)
+        }
+        [b'\\', c, ..] => {
+            let value = match c {
+                b'n' => b'\n',
+                b't' => b'\t',
+                b'r' => b'\r',
+                b'0' => 0,
+                b'a' => 0x7,
+                b'b' => 0x8,
+                b'f' => 0xc,
+                b'v' => 0xb,
+                c => *c,
+            };
+            (value, 2)
+        }
+        [c, ..] if *c != b'\'' => (*c, 1),
+        _ => return Err(anyhow!("Empty character literal")),
+    };
+    if bytes.get(len) != Some(&b'\'') {
+        return Err(anyhow!("Unterminated character literal"));
+    }
+    Ok((value, len + 1))
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Int { value, .. } => write!(f, "`{}`", value),
+            Token::Float(value) => write!(f, "`{}`", value),
+            Token::Char(value) => write!(f, "`'{}'`", std::ascii::escape_default(*value)),
+            Token::Ident(name) => write!(f, "`{}`", name),
+            Token::Register(name) => write!(f, "`${}`", name),
+            Token::History(HistoryIndex::Absolute(n)) => write!(f, "`${}`", n),
+            Token::History(HistoryIndex::Relative(n)) => write!(f, "`$${}`", n),
+            Token::Punct(punct) => write!(f, "`{}`", punct),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(value: u64) -> Token {
+        Token::Int {
+            value,
+            unsigned: false,
+            long: false,
+            decimal: true,
+        }
+    }
+
+    fn hex_or_octal(value: u64) -> Token {
+        Token::Int {
+            value,
+            unsigned: false,
+            long: false,
+            decimal: false,
+        }
+    }
+
+    fn ident(name: &str) -> Token {
+        Token::Ident(name.to_owned())
+    }
+
+    // Expressions of constants are evaluated with no process
+    fn with_context<T>(f: impl FnOnce(&mut Context) -> T) -> T {
+        let mut types = TypeTable::default();
+        let mut unwinder = Unwinder::default();
+        let mut ctx = Context::new(None, &mut types, &mut unwinder, &[], 0);
+        f(&mut ctx)
+    }
+
+    fn evaluate_int(input: &str) -> u64 {
+        with_context(|ctx| {
+            let value = ctx.evaluate(input).unwrap();
+            ctx.get_u64(&value).unwrap()
+        })
+    }
+
+    fn type_of(input: &str) -> String {
+        with_context(|ctx| {
+            let value = ctx.evaluate(input).unwrap();
+            ctx.types.name(value.ty)
+        })
+    }
+
+    #[test]
+    fn tokenize_operators() {
+        assert_eq!(
+            tokenize("p->next[2]->value<<=1").unwrap(),
+            [
+                ident("p"),
+                Token::Punct("->"),
+                ident("next"),
+                Token::Punct("["),
+                int(2),
+                Token::Punct("]"),
+                Token::Punct("->"),
+                ident("value"),
+                Token::Punct("<<"),
+                Token::Punct("="),
+                int(1),
+            ]
+        );
+        assert_eq!(
+            tokenize("!a&&~b||c").unwrap(),
+            [
+                Token::Punct("!"),
+                ident("a"),
+                Token::Punct("&&"),
+                Token::Punct("~"),
+                ident("b"),
+                Token::Punct("||"),
+                ident("c"),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_numbers() {
+        assert_eq!(
+            tokenize("42 0x1fUL 017 10u").unwrap(),
+            [
+                int(42),
+                Token::Int {
+                    value: 0x1f,
+                    unsigned: true,
+                    long: true,
+                    decimal: false,
+                },
+                hex_or_octal(0o17),
+                Token::Int {
+                    value: 10,
+                    unsigned: true,
+                    long: false,
+                    decimal: true,
+                },
+            ]
+        );
+        // the sign belongs to the exponent of a float, but not to a hexadecimal number
+        assert_eq!(
+            tokenize("1e-5-2").unwrap(),
+            [Token::Float(1e-5), Token::Punct("-"), int(2)]
+        );
+        assert_eq!(
+            tokenize("0x1e-5").unwrap(),
+            [hex_or_octal(0x1e), Token::Punct("-"), int(5)]
+        );
+        assert_eq!(
+            tokenize(".5 2.").unwrap(),
+            [Token::Float(0.5), Token::Float(2.0)]
+        );
+        assert!(tokenize("0x").is_err());
+        assert!(tokenize("09").is_err());
+    }
+
+    #[test]
+    fn tokenize_chars() {
+        assert_eq!(
+            tokenize(r"'a' '\n' '\x41' '\''").unwrap(),
+            [
+                Token::Char(b'a'),
+                Token::Char(b'\n'),
+                Token::Char(0x41),
+                Token::Char(b'\''),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_dollars() {
+        assert_eq!(
+            tokenize("$ $3 $$ $$2 $rip $_x").unwrap(),
+            [
+                Token::History(HistoryIndex::Relative(0)),
+                Token::History(HistoryIndex::Absolute(3)),
+                Token::History(HistoryIndex::Relative(1)),
+                Token::History(HistoryIndex::Relative(2)),
+                Token::Register("rip".to_owned()),
+                Token::Register("_x".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_invalid() {
+        assert!(tokenize("a # b").is_err());
+        assert!(tokenize("a @ b").is_err());
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(evaluate_int("1 + 2 * 3"), 7);
+        assert_eq!(evaluate_int("(1 + 2) * 3"), 9);
+        assert_eq!(evaluate_int("1 << 2 + 1"), 8);
+        assert_eq!(evaluate_int("2 + 3 == 5"), 1);
+        assert_eq!(evaluate_int("7 - 2 < 4"), 0);
+        assert_eq!(evaluate_int("4 | 6 & 1"), 4);
+        assert_eq!(evaluate_int("6 ^ 3 & 1"), 7);
+        assert_eq!(evaluate_int("1 || 0 && 0"), 1);
+        assert_eq!(evaluate_int("-2 * 3 + 10"), 4);
+        assert_eq!(evaluate_int("!0 + 1"), 2);
+    }
+
+    #[test]
+    fn left_associativity() {
+        assert_eq!(evaluate_int("10 - 4 - 3"), 3);
+        assert_eq!(evaluate_int("100 / 10 / 5"), 2);
+        assert_eq!(evaluate_int("17 % 10 % 4"), 3);
+        assert_eq!(evaluate_int("256 >> 2 >> 3"), 8);
+    }
+
+    #[test]
+    fn literal_types() {
+        assert_eq!(type_of("2147483647"), "int");
+        assert_eq!(type_of("2147483648"), "long");
+        assert_eq!(type_of("0xffffffff"), "unsigned int");
+        assert_eq!(type_of("037777777777"), "unsigned int");
+        assert_eq!(type_of("0x100000000"), "long");
+        assert_eq!(type_of("0xffffffffffffffff"), "unsigned long");
+        assert_eq!(type_of("1u"), "unsigned int");
+        assert_eq!(type_of("0xffffffffL"), "long");
+    }
+
+    #[test]
+    fn needs_a_process() {
+        with_context(|ctx| {
+            assert!(ctx.evaluate("$rip").is_err());
+            assert!(ctx.evaluate("counter").is_err());
+            let value = ctx.evaluate("*(int *)0x1000").unwrap();
+            assert!(ctx.read(&value).is_err());
+        });
+    }
+}
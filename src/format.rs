@@ -0,0 +1,240 @@
+use crate::{
+    tracee::Tracee,
+    types::{Bitfield, Type, TypeId, TypeTable},
+    watchpoint,
+};
+
+// Structs and arrays nested deeper than this are elided as `{...}`
+const MAX_DEPTH: usize = 8;
+// Limit on the elements of arrays and the characters of strings that are shown
+const MAX_ELEMENTS: usize = 200;
+
+// Renders the bytes of a value of the type the way C would write it.
+// Memory is read from the tracee to show what pointers point to.
+pub fn format_value(tracee: &Tracee, types: &TypeTable, ty: TypeId, bytes: &[u8]) -> String {
+    format_nested(tracee, types, ty, bytes, 0)
+}
+
+fn format_nested(
+    tracee: &Tracee,
+    types: &TypeTable,
+    ty: TypeId,
+    bytes: &[u8],
+    depth: usize,
+) -> String {
+    match types.get(types.strip(ty)) {
+        Type::Void => "void".to_owned(),
+        Type::Base { encoding, .. } => format_base(*encoding, bytes),
+        Type::Enum {
+            variants,
+            underlying,
+            ..
+        } => {
+            let unsigned = underlying.is_some_and(|underlying| {
+                matches!(
+                    types.get(types.strip(underlying)),
+                    Type::Base {
+                        encoding: gimli::DW_ATE_unsigned,
+                        ..
+                    }
+                )
+            });
+            let value = if unsigned {
+                to_u64(bytes) as i64
+            } else {
+                to_i64(bytes)
+            };
+            match variants.iter().find(|(_, v)| *v == value) {
+                Some((name, _)) => name.clone(),
+                None => value.to_string(),
+            }
+        }
+        Type::Pointer { target, .. } => format_pointer(tracee, types, ty, *target, bytes, depth),
+        Type::Struct { members, .. } => {
+            if depth >= MAX_DEPTH {
+                return "{...}".to_owned();
+            }
+            let fields = members
+                .iter()
+                .map(|member| {
+                    let value = match member.bitfield {
+                        Some(bf) => {
+                            let value_bytes = extract_bitfield(types, member.ty, bf, bytes);
+                            format_nested(tracee, types, member.ty, &value_bytes, depth + 1)
+                        }
+                        None => {
+                            let end = (member.offset + types.size(member.ty)).min(bytes.len());
+                            let start = member.offset.min(end);
+                            format_nested(tracee, types, member.ty, &bytes[start..end], depth + 1)
+                        }
+                    };
+                    match member.name {
+                        Some(ref name) => format!("{} = {}", name, value),
+                        None => value,
+                    }
+                })
+                .collect::<Vec<_>>();
+            format!("{{{}}}", fields.join(", "))
+        }
+        Type::Array { elem, .. } => {
+            if is_char(types, *elem) {
+                let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+                return quote_string(&bytes[..len.min(MAX_ELEMENTS)], len > MAX_ELEMENTS);
+            }
+            if depth >= MAX_DEPTH {
+                return "{...}".to_owned();
+            }
+            let elem_size = types.size(*elem).max(1);
+            let mut elems = bytes
+                .chunks(elem_size)
+                .take(MAX_ELEMENTS)
+                .map(|elem_bytes| format_nested(tracee, types, *elem, elem_bytes, depth + 1))
+                .collect::<Vec<_>>();
+            if bytes.len() / elem_size > MAX_ELEMENTS {
+                elems.push("...".to_owned());
+            }
+            format!("{{{}}}", elems.join(", "))
+        }
+        Type::Function { .. } => format!("{{{}}}", types.name(ty)),
+        Type::Typedef { .. } | Type::Qualified { .. } => unreachable!("stripped above"),
+    }
+}
+
+pub fn format_base(encoding: gimli::DwAte, bytes: &[u8]) -> String {
+    if bytes.len() > 8 {
+        // `long double`, `__int128`
+        return watchpoint::format_value(bytes);
+    }
+    let raw = to_u64(bytes);
+    let signed = to_i64(bytes);
+
+    match (encoding, bytes.len()) {
+        (gimli::DW_ATE_signed, _) => signed.to_string(),
+        (gimli::DW_ATE_unsigned, _) => raw.to_string(),
+        (gimli::DW_ATE_boolean, _) => (raw != 0).to_string(),
+        (gimli::DW_ATE_signed_char, 1) => format!("{} '{}'", signed, escape(raw as u8)),
+        (gimli::DW_ATE_unsigned_char, 1) => format!("{} '{}'", raw, escape(raw as u8)),
+        (gimli::DW_ATE_float, 4) => format!("{:?}", f32::from_bits(raw as u32)),
+        (gimli::DW_ATE_float, 8) => format!("{:?}", f64::from_bits(raw)),
+        _ => watchpoint::format_value(bytes),
+    }
+}
+
+// Pointers show the value they point to, if it can be read.
+// Only the outermost value is followed, so that linked lists don't get printed whole.
+fn format_pointer(
+    tracee: &Tracee,
+    types: &TypeTable,
+    ty: TypeId,
+    target: TypeId,
+    bytes: &[u8],
+    depth: usize,
+) -> String {
+    let addr = to_u64(bytes);
+    if addr != 0 && is_char(types, target) {
+        return match read_string(tracee, addr) {
+            Some((s, truncated)) => format!("{:#x} {}", addr, quote_string(&s, truncated)),
+            None => format!(
+                "{:#x} <error: Cannot access memory at address {:#x}>",
+                addr, addr
+            ),
+        };
+    }
+    if depth > 0 {
+        return format!("{:#x}", addr);
+    }
+
+    let mut s = format!("({}) {:#x}", types.name(ty), addr);
+    let target_ty = types.get(types.strip(target));
+    if addr != 0
+        && !matches!(target_ty, Type::Void | Type::Function { .. })
+        && let Ok(target_bytes) = tracee.read_bytes(addr, types.size(target))
+    {
+        s.push_str(" -> ");
+        s.push_str(&format_nested(
+            tracee,
+            types,
+            target,
+            &target_bytes,
+            depth + 1,
+        ));
+    }
+    s
+}
+
+fn is_char(types: &TypeTable, ty: TypeId) -> bool {
+    matches!(
+        types.get(types.strip(ty)),
+        Type::Base {
+            encoding: gimli::DW_ATE_signed_char | gimli::DW_ATE_unsigned_char,
+            size: 1,
+            ..
+        }
+    )
+}
+
+// Reads the NUL terminated string at `addr`, a word at a time as the end of it is unknown.
+// Also returns whether the string was cut off at `MAX_ELEMENTS` characters.
+pub fn read_string(tracee: &Tracee, addr: u64) -> Option<(Vec<u8>, bool)> {
+    let mut s = Vec::new();
+    let mut word_addr = addr & !0x7;
+    let mut skip = (addr - word_addr) as usize;
+    loop {
+        let word = tracee.read_bytes(word_addr, 8).ok()?;
+        for &b in &word[skip..] {
+            if b == 0 {
+                return Some((s, false));
+            }
+            if s.len() == MAX_ELEMENTS {
+                return Some((s, true));
+            }
+            s.push(b);
+        }
+        word_addr += 8;
+        skip = 0;
+    }
+}
+
+pub fn quote_string(s: &[u8], truncated: bool) -> String {
+    let escaped = s.iter().map(|&b| escape(b)).collect::<String>();
+    format!("\"{}\"{}", escaped, if truncated { "..." } else { "" })
+}
+
+pub fn escape(b: u8) -> String {
+    std::ascii::escape_default(b).to_string()
+}
+
+// The bits of the bitfield, as the bytes of a value of its type
+pub fn extract_bitfield(types: &TypeTable, ty: TypeId, bf: Bitfield, bytes: &[u8]) -> Vec<u8> {
+    let start = (bf.bit_offset / 8) as usize;
+    let shift = bf.bit_offset % 8;
+    let mut buf = [0; 16];
+    let end = (start + 16).min(bytes.len());
+    buf[..end.saturating_sub(start)].copy_from_slice(&bytes[start.min(end)..end]);
+
+    let mut value = (u128::from_le_bytes(buf) >> shift) & ((1 << bf.bit_size) - 1);
+    let signed = matches!(
+        types.get(types.strip(ty)),
+        Type::Base {
+            encoding: gimli::DW_ATE_signed | gimli::DW_ATE_signed_char,
+            ..
+        }
+    );
+    if signed && value >> (bf.bit_size - 1) & 1 == 1 {
+        value |= !0 << bf.bit_size;
+    }
+    value.to_le_bytes()[..types.size(ty).min(16)].to_vec()
+}
+
+pub fn to_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0; 8];
+    let len = bytes.len().min(8);
+    buf[..len].copy_from_slice(&bytes[..len]);
+    u64::from_le_bytes(buf)
+}
+
+// Sign extends the value from its actual size
+pub fn to_i64(bytes: &[u8]) -> i64 {
+    let shift = 64 - 8 * bytes.len().clamp(1, 8) as u32;
+    ((to_u64(bytes) << shift) as i64) >> shift
+}
@@ -0,0 +1,182 @@
+use anyhow::{anyhow, Result};
+use nix::libc;
+use nix::sys::{ptrace, signal::Signal, wait};
+use nix::unistd::Pid;
+
+use crate::{
+    abi::{self, Class},
+    breakpoint, registers,
+    registers::Register,
+    tracee::Tracee,
+    types::{self, TypeId, TypeTable},
+};
+
+// Bytes below the stack pointer that the interrupted function may still be using
+const RED_ZONE: u64 = 128;
+
+// The registers the System V x86-64 ABI passes the integer arguments in, in order
+const INT_ARG_REGS: [Register; 6] = [
+    Register::Rdi,
+    Register::Rsi,
+    Register::Rdx,
+    Register::Rcx,
+    Register::R8,
+    Register::R9,
+];
+// `xmm0` to `xmm7`
+const SSE_ARG_REGS: usize = 8;
+
+// Calls the function at `func` in the tracee with the arguments, given as their types and bytes,
+// and returns the bytes of the value it returns.
+// The function runs on the tracee's stack, below what is in use, and returns to a breakpoint
+// at the entry point of the executable, which doesn't run again.
+// The registers are restored afterwards, including when the call doesn't return.
+pub fn call_function(
+    tracee: &mut Tracee,
+    types: &TypeTable,
+    func: u64,
+    ret: TypeId,
+    args: &[(TypeId, Vec<u8>)],
+) -> Result<Vec<u8>> {
+    let regs = ptrace::getregs(tracee.pid)?;
+    let fp_regs = registers::get_fp_regs(tracee.pid)?;
+
+    let res = run_call(tracee, types, func, ret, args, regs);
+
+    // fails when the tracee is gone, which the error of the call explains
+    let restored = ptrace::setregs(tracee.pid, regs)
+        .map_err(anyhow::Error::from)
+        .and_then(|_| registers::set_fp_regs(tracee.pid, &fp_regs));
+    match (res, restored) {
+        (Ok(value), Ok(())) => Ok(value),
+        (Err(e), Ok(())) => Err(anyhow!("{}\nThe state of the program was restored.", e)),
+        (Err(e), Err(_)) | (Ok(_), Err(e)) => Err(e),
+    }
+}
+
+fn run_call(
+    tracee: &mut Tracee,
+    types: &TypeTable,
+    func: u64,
+    ret: TypeId,
+    args: &[(TypeId, Vec<u8>)],
+    mut regs: libc::user_regs_struct,
+) -> Result<Vec<u8>> {
+    let mut fp_regs = registers::get_fp_regs(tracee.pid)?;
+    let mut sp = regs.rsp - RED_ZONE;
+    let mut int_args = Vec::new();
+    let mut sse_args = Vec::new();
+
+    // a value returned in memory goes to a buffer the caller passes as a hidden first argument
+    let ret_is_void = types.strip(ret) == types::VOID;
+    if !ret_is_void && abi::classify(types, ret)? == [Class::Memory] {
+        sp = (sp - types.size(ret) as u64) & !0xf;
+        int_args.push(sp);
+    }
+
+    // the arguments that don't fit in the registers left go on the stack, in order
+    let mut stack_args = Vec::new();
+    for (ty, bytes) in args {
+        let classes = abi::classify(types, *ty)?;
+        let ints = classes.iter().filter(|&&c| c == Class::Integer).count();
+        let sses = classes.iter().filter(|&&c| c == Class::Sse).count();
+        let in_regs = !matches!(classes[..], [Class::Memory] | [Class::X87])
+            && int_args.len() + ints <= INT_ARG_REGS.len()
+            && sse_args.len() + sses <= SSE_ARG_REGS;
+        if !in_regs {
+            stack_args.push(bytes);
+            continue;
+        }
+        for (class, chunk) in classes.iter().zip(bytes.chunks(8)) {
+            let mut eightbyte = [0; 8];
+            eightbyte[..chunk.len()].copy_from_slice(chunk);
+            match class {
+                Class::Integer => int_args.push(u64::from_le_bytes(eightbyte)),
+                Class::Sse => sse_args.push([u64::from_le_bytes(eightbyte), 0]),
+                // the rest of the `xmm` register of the eightbyte before
+                Class::SseUp => {
+                    sse_args.last_mut().expect("follows an `Sse` eightbyte")[1] =
+                        u64::from_le_bytes(eightbyte)
+                }
+                Class::X87 | Class::Empty | Class::Memory => {}
+            }
+        }
+    }
+
+    // each argument takes whole eightbytes, the first one at a 16 byte boundary
+    // right above the return address
+    let stack_len = stack_args
+        .iter()
+        .map(|bytes| bytes.len().div_ceil(8) as u64 * 8)
+        .sum::<u64>();
+    sp = (sp - stack_len) & !0xf;
+    let mut addr = sp;
+    for bytes in stack_args {
+        tracee.write_bytes(addr, bytes)?;
+        addr += bytes.len().div_ceil(8) as u64 * 8;
+    }
+
+    let ret_addr = tracee.entry_point();
+    sp -= 8;
+    tracee.write_bytes(sp, &ret_addr.to_le_bytes())?;
+
+    for (reg, value) in INT_ARG_REGS.iter().zip(int_args) {
+        *registers::get_mutable_reg(&mut regs, *reg) = value;
+    }
+    for (n, [low, high]) in sse_args.iter().enumerate() {
+        registers::set_xmm_low(&mut fp_regs, n, *low);
+        registers::set_xmm_high(&mut fp_regs, n, *high);
+    }
+    // variadic functions are told how many vector registers hold arguments
+    regs.rax = sse_args.len() as u64;
+    regs.rsp = sp;
+    regs.rip = func;
+    // the system call the tracee might be stopped in isn't restarted on the way
+    regs.orig_rax = u64::MAX;
+    ptrace::setregs(tracee.pid, regs)?;
+    registers::set_fp_regs(tracee.pid, &fp_regs)?;
+
+    let orig = tracee.read_bytes(ret_addr, 1)?;
+    tracee.write_bytes(ret_addr, &[breakpoint::INT3])?;
+    let returned = wait_for_return(tracee.pid, ret_addr);
+    let removed = tracee.write_bytes(ret_addr, &orig);
+    returned?;
+    removed?;
+
+    if ret_is_void {
+        return Ok(Vec::new());
+    }
+    abi::get_return_value(tracee, types, ret)
+}
+
+// Runs the tracee until it traps on the int3 at the return address.
+// Stopping anywhere else abandons the call, the signal that stopped it isn't delivered.
+fn wait_for_return(pid: Pid, ret_addr: u64) -> Result<()> {
+    ptrace::cont(pid, None)?;
+    match wait::waitpid(pid, None)? {
+        wait::WaitStatus::Stopped(_, Signal::SIGTRAP)
+            if registers::get_reg_value(pid, Register::Rip)? == ret_addr + 1 =>
+        {
+            Ok(())
+        }
+        wait::WaitStatus::Stopped(_, Signal::SIGTRAP) => Err(anyhow!(
+            "The program being debugged stopped while in a function called from the debugger."
+        )),
+        wait::WaitStatus::Stopped(_, sig) => Err(anyhow!(
+            "The program being debugged was signaled while in a function called from the debugger ({}).",
+            sig
+        )),
+        wait::WaitStatus::Exited(_, code) => Err(anyhow!(
+            "The program being debugged exited with code {} while in a function called from the debugger.",
+            code
+        )),
+        wait::WaitStatus::Signaled(_, sig, _) => Err(anyhow!(
+            "The program being debugged was killed by {} while in a function called from the debugger.",
+            sig
+        )),
+        status => Err(anyhow!(
+            "Unexpected stop while in a function called from the debugger: {:?}",
+            status
+        )),
+    }
+}
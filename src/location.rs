@@ -0,0 +1,41 @@
+use anyhow::{anyhow, Result};
+use std::str::FromStr;
+
+use crate::util;
+
+// A place in the tracee's code, as specified by the user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    // `*<expr>`: an absolute address in the running process, like `*0x401136` or `*main+4`
+    Address(String),
+    // `0x...`: an address relative to the start of the binary
+    Offset(u64),
+    // `main`
+    Function(String),
+    // `test2.c:22`, or `22` for a line of the current file, which is left empty
+    Line(String, usize),
+}
+
+impl FromStr for Location {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let loc = if let Some(addr) = s.strip_prefix('*') {
+            Location::Address(addr.to_owned())
+        } else if s.starts_with("0x") {
+            Location::Offset(util::parse_hex(s)?)
+        } else if let Some((file, line)) = s.rsplit_once(':') {
+            let line = line
+                .parse()
+                .map_err(|_| anyhow!("Invalid line number: {}", line))?;
+            Location::Line(file.to_owned(), line)
+        } else if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+            Location::Line(String::new(), s.parse()?)
+        } else if !s.is_empty() {
+            Location::Function(s.to_owned())
+        } else {
+            return Err(anyhow!("No location provided"));
+        };
+        Ok(loc)
+    }
+}
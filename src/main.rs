@@ -1,18 +1,36 @@
 #![feature(let_chains)]
 #![feature(trivial_bounds)]
 
+mod abi;
 mod breakpoint;
+mod cli;
+mod debug_registers;
 mod debugger;
+mod disasm;
 mod dwarf;
+mod examine;
+mod expr;
+mod format;
+mod infcall;
+mod location;
+mod memory;
+mod ptype;
 mod registers;
+mod skip;
 mod tracee;
+mod types;
+mod unwind;
 mod util;
+mod variable;
+mod watchpoint;
 
 use anyhow::Result;
+use clap::Parser;
+use cli::Args;
 use debugger::Debugger;
 
 fn main() -> Result<()> {
-    let path = "/home/govardhan/projects/debugger/target/test2";
-    let mut debugger = Debugger::new(path)?;
+    let args = Args::parse();
+    let mut debugger = Debugger::new(&args)?;
     debugger.run()
 }
@@ -0,0 +1,121 @@
+use anyhow::{anyhow, Result};
+use nix::sys::{ptrace, uio};
+use nix::unistd::Pid;
+use std::fs::{File, OpenOptions};
+use std::io::IoSliceMut;
+use std::os::unix::fs::FileExt;
+
+// Reads `len` bytes of the tracee's memory starting at `addr`.
+// `process_vm_readv` reads the whole range with a single system call. It stops at pages the
+// tracee can't read itself, which `/proc/<pid>/mem` and then ptrace get another try at.
+pub fn read_bytes(pid: Pid, addr: u64, len: usize) -> Result<Vec<u8>> {
+    let mut bytes = vec![0; len];
+    let mut done = read_vm(pid, addr, &mut bytes).unwrap_or(0);
+    if done < len {
+        done += read_proc_mem(pid, addr + done as u64, &mut bytes[done..]).unwrap_or(0);
+    }
+    if done < len {
+        read_ptrace(pid, addr + done as u64, &mut bytes[done..])?;
+    }
+    Ok(bytes)
+}
+
+// Writes the bytes to the tracee's memory at `addr`, and nothing around them.
+// `/proc/<pid>/mem` can write to read only pages, like the ones holding the code,
+// ptrace is left for when it isn't available.
+pub fn write_bytes(pid: Pid, addr: u64, bytes: &[u8]) -> Result<()> {
+    let done = write_proc_mem(pid, addr, bytes).unwrap_or(0);
+    if done < bytes.len() {
+        write_ptrace(pid, addr + done as u64, &bytes[done..])?;
+    }
+    Ok(())
+}
+
+// Returns the number of bytes read, which is short if the range runs into a page that can't be read
+fn read_vm(pid: Pid, addr: u64, buf: &mut [u8]) -> Result<usize> {
+    if buf.is_empty() {
+        return Ok(0);
+    }
+    let remote = [uio::RemoteIoVec {
+        base: addr as usize,
+        len: buf.len(),
+    }];
+    Ok(uio::process_vm_readv(
+        pid,
+        &mut [IoSliceMut::new(buf)],
+        &remote,
+    )?)
+}
+
+fn read_proc_mem(pid: Pid, addr: u64, buf: &mut [u8]) -> Result<usize> {
+    let file = File::open(format!("/proc/{}/mem", pid))?;
+    let mut done = 0;
+    while done < buf.len() {
+        match file.read_at(&mut buf[done..], addr + done as u64)? {
+            0 => break,
+            n => done += n,
+        }
+    }
+    Ok(done)
+}
+
+fn write_proc_mem(pid: Pid, addr: u64, bytes: &[u8]) -> Result<usize> {
+    let file = OpenOptions::new()
+        .write(true)
+        .open(format!("/proc/{}/mem", pid))?;
+    let mut done = 0;
+    while done < bytes.len() {
+        match file.write_at(&bytes[done..], addr + done as u64)? {
+            0 => break,
+            n => done += n,
+        }
+    }
+    Ok(done)
+}
+
+// Reads the words covering the range one `PTRACE_PEEKDATA` at a time
+fn read_ptrace(pid: Pid, addr: u64, buf: &mut [u8]) -> Result<()> {
+    let end = addr + buf.len() as u64;
+    let mut word_addr = addr & !0x7;
+    while word_addr < end {
+        let word = peek(pid, word_addr)?.to_le_bytes();
+        for (i, &b) in word.iter().enumerate() {
+            let byte_addr = word_addr + i as u64;
+            if (addr..end).contains(&byte_addr) {
+                buf[(byte_addr - addr) as usize] = b;
+            }
+        }
+        word_addr += 8;
+    }
+    Ok(())
+}
+
+// Writes the bytes into the words covering the range, keeping the rest of the words as they were
+fn write_ptrace(pid: Pid, addr: u64, bytes: &[u8]) -> Result<()> {
+    let end = addr + bytes.len() as u64;
+    let mut word_addr = addr & !0x7;
+    while word_addr < end {
+        let mut word = peek(pid, word_addr)?.to_le_bytes();
+        for (i, b) in word.iter_mut().enumerate() {
+            let byte_addr = word_addr + i as u64;
+            if (addr..end).contains(&byte_addr) {
+                *b = bytes[(byte_addr - addr) as usize];
+            }
+        }
+        unsafe {
+            ptrace::write(
+                pid,
+                word_addr as ptrace::AddressType,
+                u64::from_le_bytes(word) as ptrace::AddressType,
+            )
+            .map_err(|_| anyhow!("Cannot access memory at address {:#x}", word_addr))?;
+        }
+        word_addr += 8;
+    }
+    Ok(())
+}
+
+fn peek(pid: Pid, addr: u64) -> Result<i64> {
+    ptrace::read(pid, addr as ptrace::AddressType)
+        .map_err(|_| anyhow!("Cannot access memory at address {:#x}", addr))
+}
@@ -0,0 +1,210 @@
+use crate::types::{Member, StructKind, Type, TypeId, TypeTable};
+
+// Width of the `/* offset | size */` column printed by `ptype /o`
+const PREFIX_WIDTH: usize = 27;
+const INDENT: &str = "    ";
+
+// Prints the type with the definitions of the structs, unions and enums it is made of,
+// e.g. `ptype struct node *`. The outermost typedef is resolved.
+// With `offsets`, the members of structs are annotated with their offset and size,
+// and the holes left for alignment are shown.
+pub fn ptype(types: &TypeTable, id: TypeId, offsets: bool) -> String {
+    let id = strip_typedefs(types, id);
+    let printer = Printer { types, offsets };
+
+    let base = declared_base(types, id);
+    let definition = match types.get(base) {
+        Type::Struct { .. } | Type::Enum { .. } => Some(printer.definition(base, 0, 0)),
+        _ => None,
+    };
+    let decl = types.declare(id, "", definition.as_deref().map(|def| (base, def)));
+
+    if offsets && matches!(types.get(base), Type::Struct { .. }) {
+        format!("/* offset      |    size */  type = {}", decl)
+    } else {
+        format!("type = {}", decl)
+    }
+}
+
+// The name of the type, resolving a single typedef when asked about the typedef itself
+pub fn whatis(types: &TypeTable, id: TypeId, is_type_name: bool) -> String {
+    match types.get(id) {
+        Type::Typedef { target, .. } if is_type_name => format!("type = {}", types.name(*target)),
+        _ => format!("type = {}", types.name(id)),
+    }
+}
+
+struct Printer<'a> {
+    types: &'a TypeTable,
+    offsets: bool,
+}
+
+impl<'a> Printer<'a> {
+    // The definition of the struct, union or enum, written where its name would go.
+    // `depth` is how deeply it is nested in the outermost struct, `base` its offset in it in bytes.
+    fn definition(&self, id: TypeId, depth: usize, base: usize) -> String {
+        let (kind, name, size, members) = match self.types.get(id) {
+            Type::Struct {
+                kind,
+                name,
+                size,
+                members,
+            } => (*kind, name, *size, members),
+            Type::Enum { name, variants, .. } => {
+                let mut next = 0;
+                let variants = variants
+                    .iter()
+                    .map(|(variant, value)| {
+                        // only the values that don't follow from the previous one are written out
+                        let s = if *value == next {
+                            variant.clone()
+                        } else {
+                            format!("{} = {}", variant, value)
+                        };
+                        next = value + 1;
+                        s
+                    })
+                    .collect::<Vec<_>>();
+                return match name {
+                    Some(name) => format!("enum {} {{{}}}", name, variants.join(", ")),
+                    None => format!("enum {{{}}}", variants.join(", ")),
+                };
+            }
+            _ => return self.types.name(id),
+        };
+
+        let mut lines = vec![match name {
+            Some(name) => format!("{} {} {{", kind.keyword(), name),
+            None => format!("{} {{", kind.keyword()),
+        }];
+        // bit just past the end of the members seen so far
+        let mut end_bit = 0;
+        for member in members {
+            let (start_bit, bit_size) = match member.bitfield {
+                Some(bf) => (bf.bit_offset, bf.bit_size),
+                None => (
+                    8 * member.offset as u64,
+                    8 * self.types.size(member.ty) as u64,
+                ),
+            };
+            if self.offsets && kind != StructKind::Union && start_bit > end_bit {
+                lines.extend(hole_lines(start_bit - end_bit, "hole"));
+            }
+            end_bit = end_bit.max(start_bit + bit_size);
+
+            let prefix = if !self.offsets {
+                String::new()
+            } else if member.bitfield.is_some() {
+                let bit = 8 * base as u64 + start_bit;
+                format!(
+                    "/* {:>6}:{:>2}   | {:>7} */",
+                    bit / 8,
+                    bit % 8,
+                    self.types.size(member.ty)
+                )
+            } else {
+                format!(
+                    "/* {:>6}      | {:>7} */",
+                    base + member.offset,
+                    self.types.size(member.ty)
+                )
+            };
+            lines.push(format!(
+                "{}{}{};",
+                prefix,
+                INDENT.repeat(depth + 1),
+                self.member_decl(member, depth + 1, base)
+            ));
+        }
+
+        if self.offsets {
+            if kind != StructKind::Union && 8 * size as u64 > end_bit {
+                lines.extend(hole_lines(8 * size as u64 - end_bit, "padding"));
+            }
+            lines.push(String::new());
+            lines.push(format!(
+                "{}{}/* total size (bytes): {:>4} */",
+                " ".repeat(PREFIX_WIDTH),
+                INDENT.repeat(depth + 1),
+                size
+            ));
+            // the outermost one lines up with `type = `
+            let indent = if depth == 0 {
+                "  ".to_owned()
+            } else {
+                INDENT.repeat(depth)
+            };
+            lines.push(format!("{}{}}}", " ".repeat(PREFIX_WIDTH), indent));
+        } else {
+            lines.push(format!("{}}}", INDENT.repeat(depth)));
+        }
+        lines.join("\n")
+    }
+
+    fn member_decl(&self, member: &Member, depth: usize, base: usize) -> String {
+        // anonymous types have to be shown in place, named structs only when looking at the layout
+        let nested = nested_definition(self.types, member.ty).filter(|&nested| {
+            match self.types.get(nested) {
+                Type::Struct { name, .. } => self.offsets || name.is_none(),
+                Type::Enum { name, .. } => name.is_none(),
+                _ => false,
+            }
+        });
+        let definition = nested.map(|nested| self.definition(nested, depth, base + member.offset));
+        let expanded = nested.zip(definition.as_deref());
+
+        let decl = self
+            .types
+            .declare(member.ty, member.name.as_deref().unwrap_or(""), expanded);
+        match member.bitfield {
+            Some(bf) => format!("{} : {}", decl, bf.bit_size),
+            None => decl,
+        }
+    }
+}
+
+// The `XXX` lines describing a gap of `bits` between members, or at the end of a struct
+fn hole_lines(bits: u64, what: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    if !bits.is_multiple_of(8) {
+        let text = format!("XXX {:>2}-bit {}", bits % 8, what);
+        lines.push(format!("/* {:<21} */", text));
+    }
+    if bits / 8 != 0 {
+        let text = format!("XXX {:>2}-byte {}", bits / 8, what);
+        lines.push(format!("/* {:<21} */", text));
+    }
+    lines
+}
+
+// The struct, union or enum stored directly in a member of the type, possibly as an array
+fn nested_definition(types: &TypeTable, mut id: TypeId) -> Option<TypeId> {
+    loop {
+        match types.get(id) {
+            Type::Struct { .. } | Type::Enum { .. } => return Some(id),
+            Type::Qualified { target, .. } => id = *target,
+            Type::Array { elem, .. } => id = *elem,
+            _ => return None,
+        }
+    }
+}
+
+// The struct, union or enum at the bottom of pointers, arrays and function types,
+// whose definition `ptype` shows
+fn declared_base(types: &TypeTable, mut id: TypeId) -> TypeId {
+    loop {
+        match types.get(id) {
+            Type::Pointer { target, .. } | Type::Qualified { target, .. } => id = *target,
+            Type::Array { elem, .. } => id = *elem,
+            Type::Function { ret, .. } => id = *ret,
+            _ => return id,
+        }
+    }
+}
+
+fn strip_typedefs(types: &TypeTable, mut id: TypeId) -> TypeId {
+    while let Type::Typedef { target, .. } = types.get(id) {
+        id = *target;
+    }
+    id
+}
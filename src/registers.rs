@@ -1,7 +1,9 @@
 use anyhow::{anyhow, Result};
+use nix::errno::Errno;
 use nix::libc;
 use nix::sys::ptrace;
 use nix::unistd::Pid;
+use std::collections::HashMap;
 
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy)]
@@ -56,6 +58,65 @@ pub fn set_reg_value(pid: Pid, reg: Register, value: u64) -> Result<()> {
     Ok(())
 }
 
+// The x87 and SSE registers, which `getregs` leaves out
+pub fn get_fp_regs(pid: Pid) -> Result<libc::user_fpregs_struct> {
+    let mut regs = std::mem::MaybeUninit::<libc::user_fpregs_struct>::uninit();
+    let res = unsafe {
+        libc::ptrace(
+            libc::PTRACE_GETFPREGS,
+            pid.as_raw(),
+            std::ptr::null_mut::<libc::c_void>(),
+            regs.as_mut_ptr(),
+        )
+    };
+    Errno::result(res)?;
+    Ok(unsafe { regs.assume_init() })
+}
+
+pub fn set_fp_regs(pid: Pid, regs: &libc::user_fpregs_struct) -> Result<()> {
+    let res = unsafe {
+        libc::ptrace(
+            libc::PTRACE_SETFPREGS,
+            pid.as_raw(),
+            std::ptr::null_mut::<libc::c_void>(),
+            regs as *const libc::user_fpregs_struct,
+        )
+    };
+    Errno::result(res)?;
+    Ok(())
+}
+
+// The low 8 bytes of `xmm<n>`, where a `double` is kept
+pub fn get_xmm_low(regs: &libc::user_fpregs_struct, n: usize) -> u64 {
+    (regs.xmm_space[4 * n] as u64) | (regs.xmm_space[4 * n + 1] as u64) << 32
+}
+
+pub fn set_xmm_low(regs: &mut libc::user_fpregs_struct, n: usize, value: u64) {
+    regs.xmm_space[4 * n] = value as u32;
+    regs.xmm_space[4 * n + 1] = (value >> 32) as u32;
+}
+
+// The high 8 bytes of `xmm<n>`, only used by 16 byte values like `__float128`
+pub fn get_xmm_high(regs: &libc::user_fpregs_struct, n: usize) -> u64 {
+    (regs.xmm_space[4 * n + 2] as u64) | (regs.xmm_space[4 * n + 3] as u64) << 32
+}
+
+pub fn set_xmm_high(regs: &mut libc::user_fpregs_struct, n: usize, value: u64) {
+    regs.xmm_space[4 * n + 2] = value as u32;
+    regs.xmm_space[4 * n + 3] = (value >> 32) as u32;
+}
+
+// Values of the general purpose registers, keyed by their DWARF register number
+pub fn get_dwarf_reg_values(pid: Pid) -> Result<HashMap<u16, u64>> {
+    let regs = ptrace::getregs(pid)?;
+    let mut values = HashMap::new();
+    for dwarf in 0..=15 {
+        let reg = get_reg_from_dwarf_number(dwarf)?;
+        values.insert(dwarf as u16, *get_reg(&regs, reg));
+    }
+    Ok(values)
+}
+
 pub fn get_reg_from_string(s: &str) -> Result<Register> {
     let reg = match s.to_ascii_lowercase().as_str() {
         "rax" => Register::Rax,
@@ -65,8 +126,8 @@ pub fn get_reg_from_string(s: &str) -> Result<Register> {
 
         "rdi" => Register::Rdi,
         "rsi" => Register::Rsi,
-        "rbp" => Register::Rbp,
-        "rsp" => Register::Rsp,
+        "rbp" | "fp" => Register::Rbp,
+        "rsp" | "sp" => Register::Rsp,
 
         "r8" => Register::R8,
         "r9" => Register::R9,
@@ -78,7 +139,7 @@ pub fn get_reg_from_string(s: &str) -> Result<Register> {
         "r14" => Register::R14,
         "r15" => Register::R15,
 
-        "rip" => Register::Rip,
+        "rip" | "pc" => Register::Rip,
         "rflags" => Register::Rflags,
         "cs" => Register::Cs,
 
@@ -222,7 +283,7 @@ fn get_reg(regs: &libc::user_regs_struct, reg: Register) -> &libc::c_ulonglong {
     }
 }
 
-fn get_mutable_reg(regs: &mut libc::user_regs_struct, reg: Register) -> &mut libc::c_ulonglong {
+pub fn get_mutable_reg(regs: &mut libc::user_regs_struct, reg: Register) -> &mut libc::c_ulonglong {
     match reg {
         Register::Rax => &mut regs.rax,
         Register::Rbx => &mut regs.rbx,
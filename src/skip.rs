@@ -0,0 +1,129 @@
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipKind {
+    File,
+    Function,
+}
+
+// A file or a function `step` doesn't stop in, running the functions matching it until they return.
+// The pattern can be a glob, with `*` and `?`.
+#[derive(Debug)]
+pub struct Skip {
+    pub id: usize,
+    pub kind: SkipKind,
+    pub pattern: String,
+}
+
+impl Skip {
+    // Files are matched by their name alone, unless the pattern has a directory in it
+    pub fn matches(&self, func: Option<&str>, path: &Path) -> bool {
+        match self.kind {
+            SkipKind::Function => func.is_some_and(|func| glob_match(&self.pattern, func)),
+            SkipKind::File if self.pattern.contains('/') => {
+                glob_match(&self.pattern, &path.to_string_lossy())
+            }
+            SkipKind::File => path
+                .file_name()
+                .is_some_and(|name| glob_match(&self.pattern, &name.to_string_lossy())),
+        }
+    }
+}
+
+// `*` matches any number of characters, `?` a single one
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+
+    // where to resume from when what followed the last `*` doesn't match
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut t) = (0, 0);
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                // let the `*` take one more character
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_literal() {
+        assert!(glob_match("main", "main"));
+        assert!(!glob_match("main", "mai"));
+        assert!(!glob_match("main", "mains"));
+        assert!(glob_match("", ""));
+        assert!(!glob_match("", "a"));
+    }
+
+    #[test]
+    fn glob_question_mark() {
+        assert!(glob_match("f?o", "foo"));
+        assert!(!glob_match("f?o", "fo"));
+        assert!(!glob_match("?", ""));
+    }
+
+    #[test]
+    fn glob_star() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("std::*", "std::vector::push_back"));
+        assert!(glob_match("*.h", "vector.h"));
+        assert!(!glob_match("*.h", "vector.c"));
+        assert!(glob_match("a*b*c", "abc"));
+        assert!(glob_match("a**c", "abbc"));
+        assert!(!glob_match("a*b", "acb c"));
+    }
+
+    #[test]
+    fn glob_backtracking() {
+        // the first `b` isn't the one the `*` has to stop at
+        assert!(glob_match("*bc", "abbc"));
+        assert!(glob_match("a*b?d", "abxbcd"));
+        assert!(glob_match("*x*y", "xaxby"));
+        assert!(!glob_match("*x*y", "xaxbx"));
+    }
+
+    #[test]
+    fn skip_file_name_or_path() {
+        let skip = Skip {
+            id: 1,
+            kind: SkipKind::File,
+            pattern: "*.h".to_owned(),
+        };
+        assert!(skip.matches(None, Path::new("/usr/include/stdio.h")));
+        let skip = Skip {
+            id: 2,
+            kind: SkipKind::File,
+            pattern: "/usr/*".to_owned(),
+        };
+        assert!(skip.matches(None, Path::new("/usr/include/stdio.h")));
+        assert!(!skip.matches(None, Path::new("/home/usr/main.c")));
+        let skip = Skip {
+            id: 3,
+            kind: SkipKind::Function,
+            pattern: "get_*".to_owned(),
+        };
+        assert!(skip.matches(Some("get_value"), Path::new("main.c")));
+        assert!(!skip.matches(None, Path::new("main.c")));
+    }
+}
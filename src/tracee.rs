@@ -1,38 +1,67 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use gimli::{Dwarf, EndianSlice, RunTimeEndian, UnitOffset};
 use nix::errno::Errno;
 use nix::sys::{personality, ptrace, signal::Signal, wait};
-use object::Object;
+use nix::unistd::Pid;
+use object::{Object, ObjectSegment, ObjectSymbol};
+use std::collections::HashMap;
+use std::fs::File;
+use std::ops::Range;
 use std::os::unix::process::CommandExt;
-use std::process::Command;
+use std::path::Path;
+use std::process::{Command, Stdio};
 
-use crate::{registers, util};
+use crate::{
+    breakpoint::{Breakpoint, INT3},
+    cli::Args,
+    location::Location,
+    memory, registers, util,
+};
 
 #[derive(Debug)]
 pub struct Tracee {
-    pub pid: nix::unistd::Pid,
+    pub pid: Pid,
 
     elf: object::read::File<'static>,
     endian: gimli::RunTimeEndian,
 
     pub start_load_addr: u64,
+
+    // the int3 instructions patched into the tracee, reads and writes of memory go around them
+    pub breakpoints: HashMap<ptrace::AddressType, Breakpoint>,
 }
 
 impl Tracee {
-    pub fn new(path: &str) -> Result<Self> {
+    pub fn new(args: &Args) -> Result<Self> {
+        let path = args
+            .program
+            .as_ref()
+            .ok_or(anyhow!("No program provided"))?;
         let mut cmd = Command::new(path);
-        // cmd
-        // .stdout(std::process::Stdio::null())
-        // .stderr(std::process::Stdio::null())
-        // .arg("/etc/hosts");
+        cmd.args(&args.args).envs(args.env.iter().cloned());
+        if let Some(ref cwd) = args.cwd {
+            cmd.current_dir(cwd);
+        }
+        if let Some(ref stdin) = args.stdin {
+            cmd.stdin(Stdio::from(File::open(stdin)?));
+        }
+        if let Some(ref stdout) = args.stdout {
+            cmd.stdout(Stdio::from(File::create(stdout)?));
+        }
+        if let Some(ref stderr) = args.stderr {
+            cmd.stderr(Stdio::from(File::create(stderr)?));
+        }
 
+        let aslr = args.aslr();
         unsafe {
-            cmd.pre_exec(|| {
+            cmd.pre_exec(move || {
                 ptrace::traceme().map_err(<Errno as Into<std::io::Error>>::into)?;
 
-                let pers = personality::get()?;
-                _ = personality::set(pers | personality::Persona::ADDR_NO_RANDOMIZE)
-                    .map_err(<Errno as Into<std::io::Error>>::into)?;
+                if !aslr {
+                    let pers = personality::get()?;
+                    _ = personality::set(pers | personality::Persona::ADDR_NO_RANDOMIZE)
+                        .map_err(<Errno as Into<std::io::Error>>::into)?;
+                }
 
                 Ok(())
             });
@@ -41,6 +70,20 @@ impl Tracee {
         let child = cmd.spawn()?;
         let pid = nix::unistd::Pid::from_raw(child.id() as _);
 
+        Self::init(pid, path)
+    }
+
+    pub fn attach(pid: Pid) -> Result<Self> {
+        ptrace::attach(pid)?;
+        Self::init(pid, format!("/proc/{}/exe", pid))
+    }
+
+    pub fn detach(&self) -> Result<()> {
+        ptrace::detach(self.pid, None)?;
+        Ok(())
+    }
+
+    fn init(pid: Pid, path: impl AsRef<Path>) -> Result<Self> {
         // It should be okay to use `leak` here as we want the binary data to be present
         // for the rest of the program
         // so no need to clean it up either
@@ -54,17 +97,7 @@ impl Tracee {
         };
 
         let start_load_addr = if matches!(elf.kind(), object::ObjectKind::Dynamic) {
-            let path = format!("/proc/{}/maps", pid);
-            // let file = std::fs::File::open(path)?;
-            // let first_line = BufReader::new(file).lines().next().expect("maps file can't be empty")?;
-            let first_line = util::get_file_lines(path)?
-                .next()
-                .expect("maps file can't be empty")?;
-            let addr = first_line
-                .split('-')
-                .next()
-                .expect("maps format not followed");
-            u64::from_str_radix(addr, 16)?
+            get_load_bias(pid)?
         } else {
             0
         };
@@ -74,6 +107,7 @@ impl Tracee {
             elf,
             endian,
             start_load_addr,
+            breakpoints: HashMap::new(),
         };
         Ok(tracee)
     }
@@ -105,14 +139,21 @@ impl Tracee {
             // TRAP_BRKPT	1	/* process breakpoint */
             0x80 | 0x1 => {
                 let pc = registers::get_reg_value(self.pid, registers::Register::Rip)? - 1;
-                registers::set_reg_value(self.pid, registers::Register::Rip, pc)?;
-                eprintln!("Hit breakpoint at address {:#x}", pc);
+                // single stepping over a syscall also reports TRAP_BRKPT,
+                // only an int3 right before the pc means that a breakpoint was hit
+                if memory::read_bytes(self.pid, pc, 1)?[0] == 0xcc {
+                    registers::set_reg_value(self.pid, registers::Register::Rip, pc)?;
+                    eprintln!("Hit breakpoint at address {:#x}", pc);
+                }
                 // self.print_source(pc)?;
             }
             // TRAP_TRACE	2	/* process trace trap */
-            0x2 => eprintln!("Received TRAP_TRACE"),
+            // nothing to report, this happens after every single step
+            0x2 => {}
             // TRAP_BRANCH  3	/* process taken branch trap */
             // TRAP_HWBKPT  4	/* hardware breakpoint/watchpoint */
+            // the debug registers are checked by the debugger to find out which one fired
+            0x4 => {}
             // TRAP_UNK	5	/* undiagnosed trap */
             // NSIGTRAP	5
             _ => eprintln!("Received unknown signal code: {:#x}", sig_info.si_code),
@@ -122,19 +163,46 @@ impl Tracee {
     }
 
     pub fn read_mem(&self, addr: u64) -> Result<i64> {
-        let val = ptrace::read(self.pid, addr as ptrace::AddressType)?;
-        Ok(val)
+        let bytes = self.read_bytes(addr, 8)?;
+        Ok(i64::from_le_bytes(
+            bytes.try_into().expect("8 bytes were read"),
+        ))
     }
 
-    pub fn write_mem(&self, addr: u64, val: u64) -> Result<()> {
-        unsafe {
-            ptrace::write(
-                self.pid,
-                addr as ptrace::AddressType,
-                val as ptrace::AddressType,
-            )?;
+    // Reads the memory as if no breakpoints were inserted, with the original instruction bytes
+    // in place of the int3s
+    pub fn read_bytes(&self, addr: u64, len: usize) -> Result<Vec<u8>> {
+        let mut bytes = memory::read_bytes(self.pid, addr, len)?;
+        let end = addr + len as u64;
+        for bp in self.breakpoints.values() {
+            let bp_addr = bp.get_addr() as u64;
+            if let Some(data) = bp.saved_byte()
+                && (addr..end).contains(&bp_addr)
+            {
+                bytes[(bp_addr - addr) as usize] = data;
+            }
         }
-        Ok(())
+        Ok(bytes)
+    }
+
+    pub fn write_mem(&mut self, addr: u64, val: u64) -> Result<()> {
+        self.write_bytes(addr, &val.to_le_bytes())
+    }
+
+    // Writing over an inserted breakpoint replaces the instruction byte it is going to restore,
+    // the int3 stays in the tracee's memory
+    pub fn write_bytes(&mut self, addr: u64, bytes: &[u8]) -> Result<()> {
+        let mut bytes = bytes.to_vec();
+        let end = addr + bytes.len() as u64;
+        for bp in self.breakpoints.values_mut() {
+            let bp_addr = bp.get_addr() as u64;
+            if bp.is_enabled() && (addr..end).contains(&bp_addr) {
+                let i = (bp_addr - addr) as usize;
+                bp.set_saved_byte(bytes[i]);
+                bytes[i] = INT3;
+            }
+        }
+        memory::write_bytes(self.pid, addr, &bytes)
     }
 
     pub fn single_step_instr(&self) -> Result<()> {
@@ -151,8 +219,14 @@ impl Tracee {
         self.start_load_addr + addr
     }
 
-    pub fn print_source(&self) -> Result<()> {
-        if let Some(le) = self.get_line_entry()? {
+    // Where the executable starts running, in the running process
+    pub fn entry_point(&self) -> u64 {
+        self.add_load_addr(self.elf.entry())
+    }
+
+    // Prints the source around the line of `pc`
+    pub fn print_source_at(&self, pc: u64) -> Result<()> {
+        if let Some(le) = self.get_line_entry_at(pc)? {
             util::print_source(le.path.display().to_string(), le.line, 2)
         } else {
             eprintln!("No source found");
@@ -160,17 +234,106 @@ impl Tracee {
         }
     }
 
+    // Returns the address in the running process that corresponds to the location
+    pub fn resolve_location(&self, loc: &Location) -> Result<u64> {
+        let addr = match loc {
+            Location::Address(expr) => {
+                return Err(anyhow!(
+                    "Address expression {} has to be evaluated in a frame",
+                    expr
+                ))
+            }
+            Location::Offset(offset) => *offset,
+            Location::Function(name) => {
+                match crate::dwarf::get_function_addr_by_name(&self.load_dwarf()?, name)? {
+                    Some(addr) => addr,
+                    // fall back to the symbol table for functions without debug info
+                    None => self
+                        .find_symbol(name, object::SymbolKind::Text)
+                        .ok_or(anyhow!("Function not found: {}", name))?,
+                }
+            }
+            Location::Line(file, line) => {
+                crate::dwarf::get_addr_for_line(&self.load_dwarf()?, file, *line)?
+                    .ok_or(anyhow!("No code found for {}:{}", file, line))?
+            }
+        };
+        Ok(self.add_load_addr(addr))
+    }
+
+    // Looks up the address of the symbol in the symbol table of the executable, relative to its start
+    pub fn find_symbol(&self, name: &str, kind: object::SymbolKind) -> Option<u64> {
+        self.elf
+            .symbols()
+            .find(|sym| sym.kind() == kind && sym.is_definition() && sym.name() == Ok(name))
+            .map(|sym| sym.address())
+    }
+
+    // Whether the address belongs to the executable, as opposed to a shared library, the stack etc.
+    pub fn is_in_executable(&self, addr: u64) -> bool {
+        let Some(offset) = addr.checked_sub(self.start_load_addr) else {
+            return false;
+        };
+        self.elf
+            .segments()
+            .any(|seg| (seg.address()..seg.address() + seg.size()).contains(&offset))
+    }
+
+    // Describes where in the source the address is, e.g. `in main at test2.c:32`
+    pub fn describe_addr(&self, addr: u64) -> Result<String> {
+        let offset_addr = self.offset_load_addr(addr);
+        let dwarf = self.load_dwarf()?;
+
+        let mut desc = Vec::new();
+        if let Some((unit, offset)) = crate::dwarf::get_function_from_pc(&dwarf, offset_addr)?
+            && let Some(name) = crate::dwarf::get_die_name(&dwarf, &unit, &unit.entry(offset)?)?
+        {
+            desc.push(format!("in {}", name));
+        }
+        if let Some(le) = crate::dwarf::get_line_entry_from_pc(&dwarf, offset_addr)? {
+            desc.push(format!("at {}:{}", le.path.display(), le.line));
+        }
+        Ok(desc.join(" "))
+    }
+
     pub fn load_dwarf(&self) -> Result<Dwarf<EndianSlice<RunTimeEndian>>, gimli::Error> {
         crate::dwarf::load_dwarf(&self.elf, self.endian)
     }
 
     pub fn get_line_entry(&self) -> Result<Option<crate::dwarf::LineEntry>> {
         let pc = registers::get_reg_value(self.pid, registers::Register::Rip)?;
+        self.get_line_entry_at(pc)
+    }
+
+    pub fn get_line_entry_at(&self, pc: u64) -> Result<Option<crate::dwarf::LineEntry>> {
+        if !self.is_in_executable(pc) {
+            return Ok(None);
+        }
         let offset_pc = self.offset_load_addr(pc);
-        println!("pc: {:#x}", offset_pc);
         crate::dwarf::get_line_entry_from_pc(&self.load_dwarf()?, offset_pc)
     }
 
+    // The name and the addresses of the function containing the address, according to the debug info
+    pub fn get_func_range_at(&self, addr: u64) -> Result<Option<(String, Range<u64>)>> {
+        if !self.is_in_executable(addr) {
+            return Ok(None);
+        }
+        let dwarf = self.load_dwarf()?;
+        let Some((unit, offset)) =
+            crate::dwarf::get_function_from_pc(&dwarf, self.offset_load_addr(addr))?
+        else {
+            return Ok(None);
+        };
+        let entry = unit.entry(offset)?;
+        let name = crate::dwarf::get_die_name(&dwarf, &unit, &entry)?.unwrap_or_default();
+        let range = crate::dwarf::get_die_bounds(&dwarf, &unit, &entry)?
+            .ok_or(anyhow!("Function {} has no addresses", name))?;
+        Ok(Some((
+            name,
+            self.add_load_addr(range.start)..self.add_load_addr(range.end),
+        )))
+    }
+
     pub fn get_func(
         &self,
     ) -> Result<Option<(gimli::Unit<EndianSlice<RunTimeEndian>, usize>, UnitOffset)>> {
@@ -181,3 +344,36 @@ impl Tracee {
         crate::dwarf::get_function_from_pc(&d, offset_pc)
     }
 }
+
+// Finds the address at which the executable's first segment is mapped,
+// by looking for the mapping of `/proc/<pid>/exe` with file offset 0
+fn get_load_bias(pid: Pid) -> Result<u64> {
+    let exe = std::fs::read_link(format!("/proc/{}/exe", pid))?;
+    let exe = exe.to_string_lossy();
+    // the kernel appends this to the link when the binary was replaced on disk
+    let exe = exe.trim_end_matches(" (deleted)");
+
+    for line in util::get_file_lines(format!("/proc/{}/maps", pid))? {
+        let line = line?;
+        // address perms offset dev inode pathname
+        let mut fields = line.split_whitespace();
+        let (Some(range), Some(_perms), Some(offset), Some(_dev), Some(_inode)) = (
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+        ) else {
+            return Err(anyhow!("maps format not followed: {}", line));
+        };
+        let path = fields.collect::<Vec<_>>().join(" ");
+        let path = path.trim_end_matches(" (deleted)");
+
+        if path == exe && u64::from_str_radix(offset, 16)? == 0 {
+            let start = range.split('-').next().expect("maps format not followed");
+            return Ok(u64::from_str_radix(start, 16)?);
+        }
+    }
+
+    Err(anyhow!("Mapping for {} not found in process {}", exe, pid))
+}
@@ -0,0 +1,526 @@
+use anyhow::{anyhow, Result};
+use gimli::{AttributeValue, Dwarf, Reader, ReaderOffset, Unit, UnitOffset};
+use std::collections::HashMap;
+
+use crate::dwarf;
+
+// Index of a type in the `TypeTable`.
+// Types refer to each other through these, as a struct can contain a pointer to itself.
+pub type TypeId = usize;
+
+pub const VOID: TypeId = 0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Void,
+    Base {
+        name: String,
+        size: usize,
+        encoding: gimli::DwAte,
+    },
+    // references are shown like the pointers they are implemented as
+    Pointer {
+        target: TypeId,
+        size: usize,
+    },
+    Struct {
+        kind: StructKind,
+        name: Option<String>,
+        size: usize,
+        members: Vec<Member>,
+    },
+    Enum {
+        name: Option<String>,
+        size: usize,
+        // the type the values are stored as, if the compiler says
+        underlying: Option<TypeId>,
+        variants: Vec<(String, i64)>,
+    },
+    // multi dimensional arrays are arrays of arrays.
+    // `count` is unknown for flexible array members and arrays declared as `extern int a[];`
+    Array {
+        elem: TypeId,
+        count: Option<usize>,
+    },
+    Typedef {
+        name: String,
+        target: TypeId,
+    },
+    Qualified {
+        qualifier: Qualifier,
+        target: TypeId,
+    },
+    Function {
+        ret: TypeId,
+        params: Vec<TypeId>,
+        variadic: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StructKind {
+    Struct,
+    Union,
+    Class,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Qualifier {
+    Const,
+    Volatile,
+    Restrict,
+    Atomic,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    // anonymous structs and unions nested in another one have no name
+    pub name: Option<String>,
+    pub ty: TypeId,
+    // in bytes from the start of the enclosing struct
+    pub offset: usize,
+    pub bitfield: Option<Bitfield>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bitfield {
+    // in bits from the start of the enclosing struct
+    pub bit_offset: u64,
+    pub bit_size: u64,
+}
+
+impl StructKind {
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            StructKind::Struct => "struct",
+            StructKind::Union => "union",
+            StructKind::Class => "class",
+        }
+    }
+}
+
+impl Qualifier {
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            Qualifier::Const => "const",
+            Qualifier::Volatile => "volatile",
+            Qualifier::Restrict => "restrict",
+            Qualifier::Atomic => "_Atomic",
+        }
+    }
+}
+
+// The types of the executable loaded so far, built from their DIEs on first use
+#[derive(Debug)]
+pub struct TypeTable {
+    types: Vec<Type>,
+    // ids of the types loaded from DIEs, by their offset in `.debug_info`
+    ids: HashMap<u64, TypeId>,
+}
+
+impl Default for TypeTable {
+    fn default() -> Self {
+        Self {
+            types: vec![Type::Void],
+            ids: HashMap::new(),
+        }
+    }
+}
+
+impl TypeTable {
+    pub fn get(&self, id: TypeId) -> &Type {
+        &self.types[id]
+    }
+
+    // Loads the type described by the DIE at `offset`, along with all the types it refers to
+    pub fn load<R: Reader>(
+        &mut self,
+        dwarf: &Dwarf<R>,
+        unit: &Unit<R>,
+        offset: UnitOffset<R::Offset>,
+    ) -> Result<TypeId> {
+        let key = offset
+            .to_debug_info_offset(&unit.header)
+            .map_or(offset.0.into_u64(), |offset| offset.0.into_u64());
+        if let Some(&id) = self.ids.get(&key) {
+            return Ok(id);
+        }
+        // registered before loading the referenced types, so that cycles end up here
+        let id = self.types.len();
+        self.types.push(Type::Void);
+        self.ids.insert(key, id);
+
+        let ty = self.load_die(dwarf, unit, offset)?;
+        self.types[id] = ty;
+        Ok(id)
+    }
+
+    fn load_die<R: Reader>(
+        &mut self,
+        dwarf: &Dwarf<R>,
+        unit: &Unit<R>,
+        offset: UnitOffset<R::Offset>,
+    ) -> Result<Type> {
+        let entry = unit.entry(offset)?;
+        let name = dwarf::get_die_name(dwarf, unit, &entry)?;
+        let size = entry
+            .attr_value(gimli::DW_AT_byte_size)?
+            .and_then(|attr| attr.udata_value())
+            .unwrap_or(0) as usize;
+        // a missing type stands for `void`
+        let target = match entry.attr_value(gimli::DW_AT_type)? {
+            Some(AttributeValue::UnitRef(target)) => self.load(dwarf, unit, target)?,
+            _ => VOID,
+        };
+
+        let ty = match entry.tag() {
+            gimli::DW_TAG_base_type => Type::Base {
+                name: name.unwrap_or_default(),
+                size,
+                encoding: match entry.attr_value(gimli::DW_AT_encoding)? {
+                    Some(AttributeValue::Encoding(encoding)) => encoding,
+                    _ => gimli::DW_ATE_unsigned,
+                },
+            },
+            gimli::DW_TAG_pointer_type
+            | gimli::DW_TAG_reference_type
+            | gimli::DW_TAG_rvalue_reference_type => Type::Pointer {
+                target,
+                size: if size == 0 {
+                    unit.encoding().address_size as usize
+                } else {
+                    size
+                },
+            },
+            gimli::DW_TAG_typedef => Type::Typedef {
+                name: name.unwrap_or_default(),
+                target,
+            },
+            tag @ (gimli::DW_TAG_const_type
+            | gimli::DW_TAG_volatile_type
+            | gimli::DW_TAG_restrict_type
+            | gimli::DW_TAG_atomic_type) => Type::Qualified {
+                qualifier: match tag {
+                    gimli::DW_TAG_const_type => Qualifier::Const,
+                    gimli::DW_TAG_volatile_type => Qualifier::Volatile,
+                    gimli::DW_TAG_restrict_type => Qualifier::Restrict,
+                    _ => Qualifier::Atomic,
+                },
+                target,
+            },
+            tag @ (gimli::DW_TAG_structure_type
+            | gimli::DW_TAG_union_type
+            | gimli::DW_TAG_class_type) => Type::Struct {
+                kind: match tag {
+                    gimli::DW_TAG_structure_type => StructKind::Struct,
+                    gimli::DW_TAG_union_type => StructKind::Union,
+                    _ => StructKind::Class,
+                },
+                name,
+                size,
+                members: self.load_members(dwarf, unit, offset)?,
+            },
+            gimli::DW_TAG_enumeration_type => Type::Enum {
+                name,
+                size,
+                underlying: (target != VOID).then_some(target),
+                variants: load_enumerators(dwarf, unit, offset)?,
+            },
+            gimli::DW_TAG_array_type => {
+                let counts = load_array_counts(unit, offset)?;
+                // the first subrange is the outermost dimension
+                let mut elem = target;
+                for &count in counts.iter().skip(1).rev() {
+                    self.types.push(Type::Array { elem, count });
+                    elem = self.types.len() - 1;
+                }
+                Type::Array {
+                    elem,
+                    count: counts.first().copied().flatten(),
+                }
+            }
+            // functions have the type of the pointers to them, minus the pointer
+            gimli::DW_TAG_subroutine_type | gimli::DW_TAG_subprogram => {
+                let mut params = Vec::new();
+                let mut variadic = false;
+                let mut tree = unit.entries_tree(Some(offset))?;
+                let mut children = tree.root()?.children();
+                while let Some(child) = children.next()? {
+                    let child = child.entry();
+                    match child.tag() {
+                        gimli::DW_TAG_formal_parameter => {
+                            if let Some(AttributeValue::UnitRef(ty)) =
+                                child.attr_value(gimli::DW_AT_type)?
+                            {
+                                params.push(self.load(dwarf, unit, ty)?);
+                            }
+                        }
+                        gimli::DW_TAG_unspecified_parameters => variadic = true,
+                        _ => {}
+                    }
+                }
+                Type::Function {
+                    ret: target,
+                    params,
+                    variadic,
+                }
+            }
+            // `decltype(nullptr)` and the like
+            _ => Type::Void,
+        };
+        Ok(ty)
+    }
+
+    fn load_members<R: Reader>(
+        &mut self,
+        dwarf: &Dwarf<R>,
+        unit: &Unit<R>,
+        offset: UnitOffset<R::Offset>,
+    ) -> Result<Vec<Member>> {
+        let mut members = Vec::new();
+        let mut tree = unit.entries_tree(Some(offset))?;
+        let mut children = tree.root()?.children();
+        while let Some(child) = children.next()? {
+            let entry = child.entry();
+            if entry.tag() != gimli::DW_TAG_member {
+                continue;
+            }
+            let Some(AttributeValue::UnitRef(ty)) = entry.attr_value(gimli::DW_AT_type)? else {
+                continue;
+            };
+            let udata = |name| -> Result<Option<u64>> {
+                Ok(entry.attr_value(name)?.and_then(|attr| attr.udata_value()))
+            };
+
+            let name = dwarf::get_die_name(dwarf, unit, entry)?;
+            let ty = self.load(dwarf, unit, ty)?;
+
+            // the members of a union all start at its beginning
+            let offset = match entry.attr_value(gimli::DW_AT_data_member_location)? {
+                Some(attr) => member_offset(unit, attr)?,
+                None => 0,
+            };
+            let bitfield = match udata(gimli::DW_AT_bit_size)? {
+                Some(bit_size) => {
+                    let bit_offset = match udata(gimli::DW_AT_data_bit_offset)? {
+                        Some(bit_offset) => bit_offset,
+                        // DWARF 2/3 count the bits from the most significant bit of the storage unit,
+                        // which is the type of the member unless given
+                        None => {
+                            let storage_size =
+                                udata(gimli::DW_AT_byte_size)?.unwrap_or(self.size(ty) as u64);
+                            let msb_offset = udata(gimli::DW_AT_bit_offset)?.unwrap_or(0);
+                            (8 * offset + 8 * storage_size)
+                                .checked_sub(msb_offset + bit_size)
+                                .ok_or(anyhow!(
+                                    "Invalid bit offset of member {}",
+                                    name.as_deref().unwrap_or("<anonymous>")
+                                ))?
+                        }
+                    };
+                    Some(Bitfield {
+                        bit_offset,
+                        bit_size,
+                    })
+                }
+                None => None,
+            };
+
+            members.push(Member {
+                name,
+                ty,
+                offset: bitfield.map_or(offset, |bf| bf.bit_offset / 8) as usize,
+                bitfield,
+            });
+        }
+        Ok(members)
+    }
+
+    // Follows typedefs and qualifiers down to the type that determines the representation
+    pub fn strip(&self, mut id: TypeId) -> TypeId {
+        while let Type::Typedef { target, .. } | Type::Qualified { target, .. } = self.types[id] {
+            id = target;
+        }
+        id
+    }
+
+    pub fn size(&self, id: TypeId) -> usize {
+        match self.types[id] {
+            // as in GNU C
+            Type::Void | Type::Function { .. } => 1,
+            Type::Base { size, .. }
+            | Type::Pointer { size, .. }
+            | Type::Struct { size, .. }
+            | Type::Enum { size, .. } => size,
+            Type::Array { elem, count } => self.size(elem) * count.unwrap_or(0),
+            Type::Typedef { target, .. } | Type::Qualified { target, .. } => self.size(target),
+        }
+    }
+
+    // The name of the type as written in C, e.g. `struct point *` or `int (*)[3]`
+    pub fn name(&self, id: TypeId) -> String {
+        self.declare(id, "", None)
+    }
+
+    // Builds the C declaration of `inner` (a name, or the declarator built so far) having the type.
+    // If given, `expanded` replaces the name of that type, to show the definition of a struct in place.
+    pub fn declare(&self, id: TypeId, inner: &str, expanded: Option<(TypeId, &str)>) -> String {
+        let with_inner = |name: &str| {
+            if inner.is_empty() {
+                name.to_owned()
+            } else {
+                format!("{} {}", name, inner)
+            }
+        };
+        if let Some((expanded_id, definition)) = expanded
+            && expanded_id == id
+        {
+            return with_inner(definition);
+        }
+
+        match &self.types[id] {
+            Type::Void => with_inner("void"),
+            Type::Base { name, .. } | Type::Typedef { name, .. } => with_inner(name),
+            Type::Struct { kind, name, .. } => with_inner(&format!(
+                "{} {}",
+                kind.keyword(),
+                name.as_deref().unwrap_or("{...}")
+            )),
+            Type::Enum { name, .. } => {
+                with_inner(&format!("enum {}", name.as_deref().unwrap_or("{...}")))
+            }
+            Type::Qualified { qualifier, target } => {
+                if let Type::Pointer { .. } = self.types[*target] {
+                    // `char * const p`, the pointer itself is const
+                    let inner = format!("{} {}", qualifier.keyword(), inner);
+                    self.declare(*target, inner.trim_end(), expanded)
+                } else {
+                    let decl = self.declare(*target, inner, expanded);
+                    format!("{} {}", qualifier.keyword(), decl)
+                }
+            }
+            Type::Pointer { target, .. } => {
+                let inner = format!("*{}", inner);
+                match self.types[*target] {
+                    Type::Array { .. } | Type::Function { .. } => {
+                        self.declare(*target, &format!("({})", inner), expanded)
+                    }
+                    _ => self.declare(*target, &inner, expanded),
+                }
+            }
+            Type::Array { elem, count } => {
+                let count = count.map(|count| count.to_string()).unwrap_or_default();
+                self.declare(*elem, &format!("{}[{}]", inner, count), expanded)
+            }
+            Type::Function {
+                ret,
+                params,
+                variadic,
+            } => {
+                let mut params = params
+                    .iter()
+                    .map(|&param| self.name(param))
+                    .collect::<Vec<_>>();
+                if *variadic {
+                    params.push("...".to_owned());
+                } else if params.is_empty() {
+                    params.push("void".to_owned());
+                }
+                let inner = format!("{}({})", inner, params.join(", "));
+                self.declare(*ret, &inner, expanded)
+            }
+        }
+    }
+
+    // Adds a type not described by the debug info, like the `int` of a literal in an expression.
+    // An identical one is reused if it was added before.
+    pub fn intern(&mut self, ty: Type) -> TypeId {
+        let existing = self.types.iter().position(|known| *known == ty);
+        existing.unwrap_or_else(|| {
+            self.types.push(ty);
+            self.types.len() - 1
+        })
+    }
+
+    // The type of a pointer to the type, made up if the program never uses one
+    pub fn pointer_to(&mut self, id: TypeId) -> TypeId {
+        let existing = self
+            .types
+            .iter()
+            .position(|ty| matches!(*ty, Type::Pointer { target, .. } if target == id));
+        existing.unwrap_or_else(|| {
+            self.types.push(Type::Pointer {
+                target: id,
+                size: std::mem::size_of::<u64>(),
+            });
+            self.types.len() - 1
+        })
+    }
+}
+
+fn load_enumerators<R: Reader>(
+    dwarf: &Dwarf<R>,
+    unit: &Unit<R>,
+    offset: UnitOffset<R::Offset>,
+) -> Result<Vec<(String, i64)>> {
+    let mut variants = Vec::new();
+    let mut tree = unit.entries_tree(Some(offset))?;
+    let mut children = tree.root()?.children();
+    while let Some(child) = children.next()? {
+        let entry = child.entry();
+        if entry.tag() != gimli::DW_TAG_enumerator {
+            continue;
+        }
+        let value = match entry.attr_value(gimli::DW_AT_const_value)? {
+            Some(AttributeValue::Sdata(value)) => value,
+            Some(attr) => attr.udata_value().unwrap_or(0) as i64,
+            None => continue,
+        };
+        if let Some(name) = dwarf::get_die_name(dwarf, unit, entry)? {
+            variants.push((name, value));
+        }
+    }
+    Ok(variants)
+}
+
+// The number of elements of each dimension of the array, outermost first
+fn load_array_counts<R: Reader>(
+    unit: &Unit<R>,
+    offset: UnitOffset<R::Offset>,
+) -> Result<Vec<Option<usize>>> {
+    let mut counts = Vec::new();
+    let mut tree = unit.entries_tree(Some(offset))?;
+    let mut children = tree.root()?.children();
+    while let Some(child) = children.next()? {
+        let entry = child.entry();
+        if entry.tag() != gimli::DW_TAG_subrange_type {
+            continue;
+        }
+        let count = match (
+            entry.attr_value(gimli::DW_AT_count)?,
+            entry.attr_value(gimli::DW_AT_upper_bound)?,
+        ) {
+            (Some(count), _) => count.udata_value(),
+            (_, Some(upper)) => upper.udata_value().map(|upper| upper + 1),
+            _ => None,
+        };
+        counts.push(count.map(|count| count as usize));
+    }
+    Ok(counts)
+}
+
+// The offset of a member in its struct.
+// DWARF 2 gives it as an expression adding it to the address of the struct.
+fn member_offset<R: Reader>(unit: &Unit<R>, attr: AttributeValue<R>) -> Result<u64> {
+    if let Some(offset) = attr.udata_value() {
+        return Ok(offset);
+    }
+    let AttributeValue::Exprloc(expr) = attr else {
+        return Err(anyhow!("Unsupported member location {:?}", attr));
+    };
+    let mut ops = expr.operations(unit.encoding());
+    match (ops.next()?, ops.next()?) {
+        (Some(gimli::Operation::PlusConstant { value }), None) => Ok(value),
+        _ => Err(anyhow!("Unsupported member location expression")),
+    }
+}
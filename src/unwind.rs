@@ -0,0 +1,390 @@
+use anyhow::{anyhow, Result};
+use gimli::{
+    BaseAddresses, CfaRule, DebugFrame, EhFrame, EndianSlice, RegisterRule, RunTimeEndian,
+    UnwindContext, UnwindSection, UnwindTableRow,
+};
+use nix::unistd::Pid;
+use object::{Object, ObjectSection, ObjectSegment, ObjectSymbol, SymbolFlags};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::PathBuf;
+
+use crate::{memory, registers, util};
+
+// DWARF register numbers of the stack pointer and the return address on x86-64
+const RSP: u16 = 7;
+const RA: u16 = 16;
+// rbx, rbp and r12 to r15, which the System V ABI has functions preserve for their callers.
+// The others are lost once a function is called.
+const CALLEE_SAVED: [u16; 6] = [3, 6, 12, 13, 14, 15];
+
+// Gives up on stacks deeper than this, they are most likely the result of a bogus unwind
+const MAX_FRAMES: usize = 512;
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub pc: u64,
+    // canonical frame address: the value of rsp in the caller, right before the call instruction.
+    // Unknown for a frame without call frame information.
+    pub cfa: Option<u64>,
+    // values of the registers recovered for this frame, keyed by their DWARF register number.
+    // Registers that couldn't be recovered are missing.
+    pub regs: HashMap<u16, u64>,
+}
+
+impl Frame {
+    // The address used to look up the source of the frame.
+    // For the callers, the pc is the return address which might already belong to the next line,
+    // or even to the next function if the call was the last instruction
+    pub fn lookup_pc(&self, index: usize) -> u64 {
+        if index == 0 {
+            self.pc
+        } else {
+            self.pc - 1
+        }
+    }
+
+    // The value of the register in this frame, if it could be recovered
+    pub fn get_reg(&self, reg: registers::Register) -> Option<u64> {
+        match reg {
+            registers::Register::Rip => Some(self.pc),
+            reg => {
+                let dwarf = registers::get_dwarf_number_from_reg(reg)?;
+                self.regs.get(&(dwarf as u16)).copied()
+            }
+        }
+    }
+}
+
+// An ELF file mapped into the tracee, the executable or a shared library
+#[derive(Debug)]
+struct Module {
+    path: PathBuf,
+    range: std::ops::Range<u64>,
+    // difference between the addresses in the file and the ones in memory
+    bias: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Unwinder {
+    // parsed ELF files, kept around as there is no need to parse them again for every backtrace.
+    // Mapped files that aren't ELF files are remembered as `None`.
+    elfs: HashMap<PathBuf, Option<object::File<'static>>>,
+}
+
+impl Unwinder {
+    // Walks up the stack of the stopped tracee using the call frame information
+    // in `.eh_frame` / `.debug_frame`, starting with the innermost frame
+    pub fn backtrace(&mut self, pid: Pid) -> Result<Vec<Frame>> {
+        let modules = self.get_modules(pid)?;
+
+        let mut regs = registers::get_dwarf_reg_values(pid)?;
+        let pc = registers::get_reg_value(pid, registers::Register::Rip)?;
+        regs.remove(&RA);
+        let mut frames = vec![Frame {
+            pc,
+            cfa: None,
+            regs,
+        }];
+
+        while frames.len() < MAX_FRAMES {
+            let index = frames.len() - 1;
+            let frame = &frames[index];
+            // the frames that can't be unwound, like PLT stubs with their CFA expressions
+            // or ones whose saved registers can't be read, end the backtrace
+            let Ok(Some((cfa, caller))) =
+                self.unwind_frame(pid, &modules, frame, frame.lookup_pc(index))
+            else {
+                break;
+            };
+            // the stack grows downwards, anything else means the unwind went wrong
+            if index > 0 && frames[index - 1].cfa.is_some_and(|prev| cfa <= prev) {
+                break;
+            }
+            frames[index].cfa = Some(cfa);
+            match caller {
+                Some(caller) if caller.pc != 0 => frames.push(caller),
+                _ => break,
+            }
+        }
+
+        Ok(frames)
+    }
+
+    // Returns `name+offset` of the ELF symbol containing the address, with the file it comes from,
+    // for code without debug info
+    pub fn symbolize(&mut self, pid: Pid, addr: u64) -> Result<Option<String>> {
+        let Some(module) = self.module_at(pid, addr)? else {
+            return Ok(None);
+        };
+        let desc = match self.symbol_in(&module, addr) {
+            Some((name, 0)) => format!("in {} from {}", name, module.path.display()),
+            Some((name, offset)) => {
+                format!("in {}+{:#x} from {}", name, offset, module.path.display())
+            }
+            None => format!("from {}", module.path.display()),
+        };
+        Ok(Some(desc))
+    }
+
+    // Returns the name of the ELF symbol containing the address and the offset of the address in it
+    pub fn symbol_at(&mut self, pid: Pid, addr: u64) -> Result<Option<(String, u64)>> {
+        let Some(module) = self.module_at(pid, addr)? else {
+            return Ok(None);
+        };
+        Ok(self.symbol_in(&module, addr))
+    }
+
+    // Looks up the function by its ELF symbol in the executable and the shared libraries mapped
+    // into the tracee, returning its address
+    pub fn find_function(&mut self, pid: Pid, name: &str) -> Result<Option<u64>> {
+        for module in self.get_modules(pid)? {
+            let elf = self.load_elf(&module.path).expect("modules are ELF files");
+            let sym = elf.symbols().chain(elf.dynamic_symbols()).find(|sym| {
+                // `is_definition` leaves out the indirect functions
+                sym.kind() == object::SymbolKind::Text
+                    && !sym.is_undefined()
+                    && sym.name() == Ok(name)
+            });
+            if let Some(sym) = sym {
+                return Ok(Some(sym.address() + module.bias));
+            }
+        }
+        Ok(None)
+    }
+
+    // Whether the function at the address is an indirect function,
+    // the resolver returning the address of the implementation to use
+    pub fn is_ifunc(&mut self, pid: Pid, addr: u64) -> Result<bool> {
+        let Some(module) = self.module_at(pid, addr)? else {
+            return Ok(false);
+        };
+        let elf = self.load_elf(&module.path).expect("modules are ELF files");
+        let file_addr = addr - module.bias;
+        Ok(elf.symbols().chain(elf.dynamic_symbols()).any(|sym| {
+            sym.address() == file_addr
+                && matches!(sym.flags(), SymbolFlags::Elf { st_info, .. }
+                    if st_info & 0xf == object::elf::STT_GNU_IFUNC)
+        }))
+    }
+
+    fn module_at(&mut self, pid: Pid, addr: u64) -> Result<Option<Module>> {
+        let modules = self.get_modules(pid)?;
+        Ok(modules.into_iter().find(|m| m.range.contains(&addr)))
+    }
+
+    // Functions are taken to extend up to the next one in their section, variables only cover their size
+    fn symbol_in(&mut self, module: &Module, addr: u64) -> Option<(String, u64)> {
+        let elf = self.load_elf(&module.path).expect("modules are ELF files");
+        let file_addr = addr - module.bias;
+        let section = elf
+            .sections()
+            .find(|sec| (sec.address()..sec.address() + sec.size()).contains(&file_addr))?
+            .index();
+
+        elf.symbols()
+            .chain(elf.dynamic_symbols())
+            .filter(|sym| {
+                let contains = match sym.kind() {
+                    object::SymbolKind::Text => true,
+                    object::SymbolKind::Data => file_addr < sym.address() + sym.size(),
+                    _ => false,
+                };
+                // `is_definition` leaves out the indirect functions
+                contains
+                    && !sym.is_undefined()
+                    && sym.section_index() == Some(section)
+                    && sym.address() <= file_addr
+            })
+            .max_by_key(|sym| sym.address())
+            .and_then(|sym| Some((sym.name().ok()?.to_owned(), file_addr - sym.address())))
+    }
+
+    fn unwind_frame(
+        &mut self,
+        pid: Pid,
+        modules: &[Module],
+        frame: &Frame,
+        lookup_pc: u64,
+    ) -> Result<Option<(u64, Option<Frame>)>> {
+        let Some(module) = modules.iter().find(|m| m.range.contains(&lookup_pc)) else {
+            return Ok(None);
+        };
+        let bias = module.bias;
+        let elf = self.load_elf(&module.path).expect("modules are ELF files");
+        let endian = if elf.is_little_endian() {
+            RunTimeEndian::Little
+        } else {
+            RunTimeEndian::Big
+        };
+
+        let mut bases = BaseAddresses::default();
+        if let Some(text) = elf.section_by_name(".text") {
+            bases = bases.set_text(text.address());
+        }
+        let mut ctx = UnwindContext::new();
+        let addr = lookup_pc - bias;
+
+        if let Some(section) = elf.section_by_name(".eh_frame") {
+            let bases = bases.clone().set_eh_frame(section.address());
+            let mut eh_frame = EhFrame::new(section.data()?, endian);
+            eh_frame.set_address_size(8);
+            if let Ok(row) =
+                eh_frame.unwind_info_for_address(&bases, &mut ctx, addr, EhFrame::cie_from_offset)
+            {
+                return apply_unwind_row(pid, frame, row).map(Some);
+            }
+        }
+        if let Some(section) = elf.section_by_name(".debug_frame") {
+            let mut debug_frame = DebugFrame::new(section.data()?, endian);
+            debug_frame.set_address_size(8);
+            if let Ok(row) = debug_frame.unwind_info_for_address(
+                &bases,
+                &mut ctx,
+                addr,
+                DebugFrame::cie_from_offset,
+            ) {
+                return apply_unwind_row(pid, frame, row).map(Some);
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn load_elf(&mut self, path: &PathBuf) -> Option<&object::File<'static>> {
+        if !self.elfs.contains_key(path) {
+            let elf = if is_elf(path) {
+                // leaked for the same reason as the executable in `Tracee`
+                std::fs::read(path)
+                    .ok()
+                    .and_then(|bin| object::File::parse(&*bin.leak()).ok())
+            } else {
+                None
+            };
+            self.elfs.insert(path.clone(), elf);
+        }
+        self.elfs[path].as_ref()
+    }
+
+    // The ELF files currently mapped into the tracee, along with where they are mapped
+    fn get_modules(&mut self, pid: Pid) -> Result<Vec<Module>> {
+        let mut modules: Vec<Module> = Vec::new();
+
+        for line in util::get_file_lines(format!("/proc/{}/maps", pid))? {
+            let line = line?;
+            // address perms offset dev inode pathname
+            let mut fields = line.split_whitespace();
+            let (Some(range), Some(_perms), Some(offset), Some(_dev), Some(_inode)) = (
+                fields.next(),
+                fields.next(),
+                fields.next(),
+                fields.next(),
+                fields.next(),
+            ) else {
+                return Err(anyhow!("maps format not followed: {}", line));
+            };
+            let path = fields.collect::<Vec<_>>().join(" ");
+            // anonymous mappings, the stack, the vdso etc.
+            if !path.starts_with('/') {
+                continue;
+            }
+            let path = PathBuf::from(path);
+
+            let (start, end) = range.split_once('-').expect("maps format not followed");
+            let start = u64::from_str_radix(start, 16)?;
+            let end = u64::from_str_radix(end, 16)?;
+            let offset = u64::from_str_radix(offset, 16)?;
+
+            if let Some(module) = modules.iter_mut().find(|m| m.path == path) {
+                module.range.start = module.range.start.min(start);
+                module.range.end = module.range.end.max(end);
+            } else if offset == 0
+                && let Some(elf) = self.load_elf(&path)
+            {
+                // the first segment is mapped at offset 0,
+                // at the address it asks for (rounded down to a page) plus the bias
+                let first_addr = elf.segments().map(|seg| seg.address()).min().unwrap_or(0);
+                modules.push(Module {
+                    bias: start - (first_addr & !0xfff),
+                    path,
+                    range: start..end,
+                });
+            }
+        }
+
+        Ok(modules)
+    }
+}
+
+// Computes the CFA of `frame` and recovers its caller's frame by applying the rules of the CFI row
+// to the registers of `frame`.
+// The outermost frame marks the return address as undefined, in which case there is no caller.
+fn apply_unwind_row(
+    pid: Pid,
+    frame: &Frame,
+    row: &UnwindTableRow<EndianSlice<RunTimeEndian>>,
+) -> Result<(u64, Option<Frame>)> {
+    let cfa = match row.cfa() {
+        CfaRule::RegisterAndOffset { register, offset } => {
+            let base = frame
+                .regs
+                .get(&register.0)
+                .ok_or(anyhow!("CFA register {} not available", register.0))?;
+            base.wrapping_add(*offset as u64)
+        }
+        CfaRule::Expression(_) => return Err(anyhow!("CFA expressions are not supported")),
+    };
+
+    // the callee saved registers without a rule keep their values
+    let mut regs = frame
+        .regs
+        .iter()
+        .filter(|(register, _)| CALLEE_SAVED.contains(register))
+        .map(|(&register, &val)| (register, val))
+        .collect::<HashMap<_, _>>();
+    let mut ret_addr = None;
+    for (register, rule) in row.registers() {
+        let val = match rule {
+            RegisterRule::Undefined => None,
+            RegisterRule::SameValue => frame.regs.get(&register.0).copied(),
+            RegisterRule::Offset(offset) => Some(read_word(pid, cfa.wrapping_add(*offset as u64))?),
+            RegisterRule::ValOffset(offset) => Some(cfa.wrapping_add(*offset as u64)),
+            RegisterRule::Register(other) => frame.regs.get(&other.0).copied(),
+            // not needed for the code compilers generate on x86-64
+            RegisterRule::Expression(_)
+            | RegisterRule::ValExpression(_)
+            | RegisterRule::Architectural => None,
+        };
+
+        if register.0 == RA {
+            ret_addr = val;
+        } else if let Some(val) = val {
+            regs.insert(register.0, val);
+        } else {
+            regs.remove(&register.0);
+        }
+    }
+    regs.insert(RSP, cfa);
+
+    let caller = ret_addr.map(|pc| Frame {
+        pc,
+        cfa: None,
+        regs,
+    });
+    Ok((cfa, caller))
+}
+
+fn read_word(pid: Pid, addr: u64) -> Result<u64> {
+    let bytes = memory::read_bytes(pid, addr, 8)?;
+    Ok(u64::from_le_bytes(
+        bytes.try_into().expect("8 bytes were read"),
+    ))
+}
+
+// Checks the magic number, to avoid reading whole data files (like the locale archive) that are mapped
+fn is_elf(path: &PathBuf) -> bool {
+    let mut magic = [0; 4];
+    std::fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok_and(|_| &magic == b"\x7fELF")
+}
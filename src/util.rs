@@ -25,6 +25,8 @@ pub fn print_source(path: impl AsRef<str>, n_line: usize, n_lines_context: usize
         + 1;
 
     for (i, line) in get_file_lines(path.as_ref())?.enumerate() {
+        // line numbers start from 1
+        let i = i + 1;
         if i < start {
             continue;
         }
@@ -0,0 +1,319 @@
+use anyhow::{anyhow, Result};
+use gimli::{
+    AttributeValue, Encoding, EndianSlice, EvaluationResult, Expression, Location, Piece,
+    RunTimeEndian, Unit, UnitOffset,
+};
+
+use crate::{
+    dwarf, format,
+    tracee::Tracee,
+    types::{TypeId, TypeTable},
+    unwind::Frame,
+};
+
+type Reader<'a> = EndianSlice<'a, RunTimeEndian>;
+
+#[derive(Debug)]
+pub struct Variable {
+    pub name: String,
+    pub is_param: bool,
+    pub ty: TypeId,
+    pub location: VarLocation,
+}
+
+#[derive(Debug)]
+pub enum VarLocation {
+    // the variable lives in memory
+    Address(u64),
+    // the variable lives in a single register, its DWARF number given along with its value
+    Register(u16, u64),
+    // the variable lives in registers, or only its value is known
+    Value(Vec<u8>),
+    // the reason the value can't be shown, like the variable being optimised out at the pc
+    Unavailable(String),
+}
+
+// The state needed to evaluate the location expressions of a frame's variables
+struct EvalContext<'a> {
+    tracee: &'a Tracee,
+    // none for the global variables, which don't live in a frame
+    frame: Option<&'a Frame>,
+    // address computed from the `DW_AT_frame_base` of the function, used by `DW_OP_fbreg`
+    frame_base: Option<u64>,
+}
+
+impl Variable {
+    // Reads the bytes of the variable, `None` if its value is unavailable
+    pub fn read(&self, tracee: &Tracee, types: &TypeTable) -> Result<Option<Vec<u8>>> {
+        let size = types.size(self.ty);
+        let bytes = match self.location {
+            VarLocation::Address(addr) => tracee.read_bytes(addr, size)?,
+            VarLocation::Register(_, value) => {
+                let mut bytes = value.to_le_bytes().to_vec();
+                bytes.resize(size, 0);
+                bytes
+            }
+            VarLocation::Value(ref bytes) => {
+                let mut bytes = bytes.clone();
+                bytes.resize(size, 0);
+                bytes
+            }
+            VarLocation::Unavailable(_) => return Ok(None),
+        };
+        Ok(Some(bytes))
+    }
+
+    pub fn format(&self, tracee: &Tracee, types: &TypeTable) -> Result<String> {
+        if let VarLocation::Unavailable(ref reason) = self.location {
+            return Ok(format!("<{}>", reason));
+        }
+        let bytes = self.read(tracee, types)?.expect("location is available");
+        Ok(format::format_value(tracee, types, self.ty, &bytes))
+    }
+}
+
+// Returns the variables and parameters visible at the pc of the frame (`index` being its level in the
+// backtrace), with the ones of the innermost scopes first
+pub fn get_frame_variables(
+    tracee: &Tracee,
+    types: &mut TypeTable,
+    frame: &Frame,
+    index: usize,
+) -> Result<Vec<Variable>> {
+    let lookup_pc = frame.lookup_pc(index);
+    if !tracee.is_in_executable(lookup_pc) {
+        return Err(anyhow!("No debug info for frame {}", index));
+    }
+    let pc = tracee.offset_load_addr(lookup_pc);
+    let dwarf = tracee.load_dwarf()?;
+    let (unit, func) = dwarf::get_function_from_pc(&dwarf, pc)?
+        .ok_or(anyhow!("No function contains the pc of frame {}", index))?;
+
+    let mut ctx = EvalContext {
+        tracee,
+        frame: Some(frame),
+        frame_base: None,
+    };
+    if let Some(AttributeValue::Exprloc(expr)) =
+        unit.entry(func)?.attr_value(gimli::DW_AT_frame_base)?
+    {
+        // only the variables relying on it are affected if it can't be computed
+        ctx.frame_base = get_frame_base(&ctx, unit.encoding(), expr).ok();
+    }
+
+    let mut vars = Vec::new();
+    for offset in dwarf::get_variables_in_scope(&dwarf, &unit, func, pc)? {
+        let entry = unit.entry(offset)?;
+        let Some(name) = dwarf::get_die_name(&dwarf, &unit, &entry)? else {
+            continue;
+        };
+        let Some(AttributeValue::UnitRef(ty)) =
+            dwarf::get_die_attr(&unit, &entry, gimli::DW_AT_type)?
+        else {
+            continue;
+        };
+
+        let location = get_location(&ctx, &dwarf, &unit, offset, pc)
+            .unwrap_or_else(|e| VarLocation::Unavailable(format!("error: {}", e)));
+        vars.push(Variable {
+            name,
+            is_param: entry.tag() == gimli::DW_TAG_formal_parameter,
+            ty: types.load(&dwarf, &unit, ty)?,
+            location,
+        });
+    }
+
+    Ok(vars)
+}
+
+// Finds the global or file static variable of that name
+pub fn find_global_variable(
+    tracee: &Tracee,
+    types: &mut TypeTable,
+    name: &str,
+) -> Result<Option<Variable>> {
+    let dwarf = tracee.load_dwarf()?;
+    let Some((unit, offset)) = dwarf::find_global_variable(&dwarf, name)? else {
+        return Ok(None);
+    };
+    let entry = unit.entry(offset)?;
+    let Some(AttributeValue::UnitRef(ty)) = dwarf::get_die_attr(&unit, &entry, gimli::DW_AT_type)?
+    else {
+        return Ok(None);
+    };
+
+    let ctx = EvalContext {
+        tracee,
+        frame: None,
+        frame_base: None,
+    };
+    // their location doesn't depend on the pc
+    let location = get_location(&ctx, &dwarf, &unit, offset, 0)
+        .unwrap_or_else(|e| VarLocation::Unavailable(format!("error: {}", e)));
+    Ok(Some(Variable {
+        name: name.to_owned(),
+        is_param: false,
+        ty: types.load(&dwarf, &unit, ty)?,
+        location,
+    }))
+}
+
+fn get_location(
+    ctx: &EvalContext,
+    dwarf: &gimli::Dwarf<Reader>,
+    unit: &Unit<Reader>,
+    offset: UnitOffset,
+    pc: u64,
+) -> Result<VarLocation> {
+    let entry = unit.entry(offset)?;
+    let expr = match entry.attr_value(gimli::DW_AT_location)? {
+        Some(AttributeValue::Exprloc(expr)) => expr,
+        // a location list, with different locations for different ranges of the function
+        Some(attr) => {
+            let mut locations = dwarf
+                .attr_locations(unit, attr)?
+                .ok_or(anyhow!("Unsupported location attribute"))?;
+            let mut expr = None;
+            while let Some(location) = locations.next()? {
+                if (location.range.begin..location.range.end).contains(&pc) {
+                    expr = Some(location.data);
+                    break;
+                }
+            }
+            let Some(expr) = expr else {
+                return Ok(VarLocation::Unavailable("optimized out".to_owned()));
+            };
+            expr
+        }
+        // constants folded by the compiler only have their value left
+        None => {
+            let bytes = match entry.attr_value(gimli::DW_AT_const_value)? {
+                Some(AttributeValue::Block(block)) => block.to_vec(),
+                Some(AttributeValue::Sdata(val)) => val.to_le_bytes().to_vec(),
+                Some(attr) => attr
+                    .udata_value()
+                    .ok_or(anyhow!("Unsupported constant value"))?
+                    .to_le_bytes()
+                    .to_vec(),
+                None => return Ok(VarLocation::Unavailable("optimized out".to_owned())),
+            };
+            return Ok(VarLocation::Value(bytes));
+        }
+    };
+
+    let pieces = evaluate(ctx, unit.encoding(), expr)?;
+    // the common cases of a variable stored as a whole in memory or in a register
+    match pieces[..] {
+        [Piece {
+            size_in_bits: None,
+            location: Location::Address { address },
+            ..
+        }] => return Ok(VarLocation::Address(address)),
+        [Piece {
+            size_in_bits: None,
+            location: Location::Register { register },
+            ..
+        }] => {
+            return Ok(match saved_reg(ctx, register) {
+                Some(value) => VarLocation::Register(register.0, value),
+                None => VarLocation::Unavailable("not saved".to_owned()),
+            })
+        }
+        _ => {}
+    }
+
+    let mut bytes = Vec::new();
+    for piece in pieces {
+        let size = piece
+            .size_in_bits
+            .map_or(8, |bits| (bits as usize).div_ceil(8));
+        let mut piece_bytes = match piece.location {
+            Location::Empty => return Ok(VarLocation::Unavailable("optimized out".to_owned())),
+            Location::Register { register } => match saved_reg(ctx, register) {
+                Some(value) => value.to_le_bytes().to_vec(),
+                None => return Ok(VarLocation::Unavailable("not saved".to_owned())),
+            },
+            Location::Address { address } => ctx.tracee.read_bytes(address, size)?,
+            Location::Value { value } => match value {
+                gimli::Value::F32(val) => val.to_le_bytes().to_vec(),
+                gimli::Value::F64(val) => val.to_le_bytes().to_vec(),
+                value => value.to_u64(!0)?.to_le_bytes().to_vec(),
+            },
+            Location::Bytes { value } => value.to_vec(),
+            Location::ImplicitPointer { .. } => {
+                return Err(anyhow!("Implicit pointers are not supported"))
+            }
+        };
+        piece_bytes.resize(size, 0);
+        bytes.extend(piece_bytes);
+    }
+    Ok(VarLocation::Value(bytes))
+}
+
+fn get_frame_base(ctx: &EvalContext, encoding: Encoding, expr: Expression<Reader>) -> Result<u64> {
+    match evaluate(ctx, encoding, expr)?[..] {
+        [Piece {
+            location: Location::Address { address },
+            ..
+        }] => Ok(address),
+        // `DW_OP_reg6` as used by clang, the register holds the frame base
+        [Piece {
+            location: Location::Register { register },
+            ..
+        }] => get_reg(ctx, register),
+        _ => Err(anyhow!("Unsupported frame base")),
+    }
+}
+
+fn evaluate<'a>(
+    ctx: &EvalContext,
+    encoding: Encoding,
+    expr: Expression<Reader<'a>>,
+) -> Result<Vec<Piece<Reader<'a>>>> {
+    let mut eval = expr.evaluation(encoding);
+    let mut result = eval.evaluate()?;
+    loop {
+        result = match result {
+            EvaluationResult::Complete => break,
+            EvaluationResult::RequiresMemory { address, size, .. } => {
+                let bytes = ctx.tracee.read_bytes(address, size as usize)?;
+                let mut buf = [0; 8];
+                buf[..bytes.len()].copy_from_slice(&bytes);
+                eval.resume_with_memory(gimli::Value::Generic(u64::from_le_bytes(buf)))?
+            }
+            EvaluationResult::RequiresRegister { register, .. } => {
+                eval.resume_with_register(gimli::Value::Generic(get_reg(ctx, register)?))?
+            }
+            EvaluationResult::RequiresFrameBase => eval.resume_with_frame_base(
+                ctx.frame_base
+                    .ok_or(anyhow!("Frame base of the function not available"))?,
+            )?,
+            EvaluationResult::RequiresCallFrameCfa => eval.resume_with_call_frame_cfa(
+                ctx.frame
+                    .and_then(|frame| frame.cfa)
+                    .ok_or(anyhow!("Canonical frame address not available"))?,
+            )?,
+            // `DW_OP_addr`, the address of a static variable in the file
+            EvaluationResult::RequiresRelocatedAddress(addr) => {
+                eval.resume_with_relocated_address(ctx.tracee.add_load_addr(addr))?
+            }
+            result => return Err(anyhow!("Unsupported DWARF expression: {:?}", result)),
+        };
+    }
+    Ok(eval.result())
+}
+
+// The value of the register in the frame.
+// Outside of the innermost frame, only the callee saved registers are known.
+fn saved_reg(ctx: &EvalContext, register: gimli::Register) -> Option<u64> {
+    ctx.frame
+        .and_then(|frame| frame.regs.get(&register.0))
+        .copied()
+}
+
+fn get_reg(ctx: &EvalContext, register: gimli::Register) -> Result<u64> {
+    saved_reg(ctx, register).ok_or(anyhow!(
+        "Register {} not available in the frame",
+        register.0
+    ))
+}
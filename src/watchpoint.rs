@@ -0,0 +1,55 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    // `watch`: stop when the value is written
+    Write,
+    // `rwatch`: stop when the value is read
+    Read,
+    // `awatch`: stop when the value is read or written
+    Access,
+}
+
+#[derive(Debug)]
+pub struct Watchpoint {
+    pub id: usize,
+    pub addr: u64,
+    pub len: usize,
+    pub kind: WatchKind,
+    // debug register the watchpoint is programmed into.
+    // Software watchpoints don't have one and are checked by single stepping the tracee instead.
+    pub slot: Option<usize>,
+    pub enabled: bool,
+    pub hit_count: usize,
+    // contents of the watched memory when it was last looked at
+    pub old_value: Vec<u8>,
+}
+
+impl Watchpoint {
+    pub fn name(&self) -> &'static str {
+        match (self.slot, self.kind) {
+            (None, _) => "watchpoint",
+            (Some(_), WatchKind::Write) => "hw watchpoint",
+            (Some(_), WatchKind::Read) => "read watchpoint",
+            (Some(_), WatchKind::Access) => "acc watchpoint",
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match (self.slot, self.kind) {
+            (None, _) => "Software watchpoint",
+            (Some(_), WatchKind::Write) => "Hardware watchpoint",
+            (Some(_), WatchKind::Read) => "Hardware read watchpoint",
+            (Some(_), WatchKind::Access) => "Hardware access (read/write) watchpoint",
+        }
+    }
+}
+
+// Values that fit in a register are shown as a number, larger ones byte by byte
+pub fn format_value(bytes: &[u8]) -> String {
+    if bytes.len() <= 8 {
+        let mut buf = [0; 8];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        format!("{:#x}", u64::from_le_bytes(buf))
+    } else {
+        format!("{:02x?}", bytes)
+    }
+}